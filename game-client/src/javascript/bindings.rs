@@ -8,7 +8,16 @@ extern "C" {
     pub fn log(s: &str);
     pub fn alert(s: &str);
     pub fn listenToSocketData(f: &Function);
+    pub fn listenToSocketLost(f: &Function);
     pub fn sendDataToSocket(data: Vec<u8>);
+    pub fn setSocketHello(data: Vec<u8>);
+    pub fn inviteCodeFromUrl() -> Option<String>;
+    pub fn inviteLink(code: &str) -> String;
+    pub fn spectateIdFromUrl() -> Option<String>;
+    pub fn replayIdFromUrl() -> Option<String>;
+    pub fn copyToClipboard(text: &str);
+    pub fn fetchLeaderboard(variant: Option<String>, days: Option<u32>, handler: &Function);
+    pub fn fetchReplay(game_id: &str, handler: &Function);
 }
 
 #[macro_export]
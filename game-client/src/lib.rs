@@ -1,159 +1,972 @@
 mod network;
 mod javascript;
 mod meta;
+mod replay;
 
-use bevy::{app::{App, Startup, Update}, asset::{AssetServer, Assets}, color::Color, core_pipeline::core_2d::Camera2d, ecs::{component::Component, entity::Entity, event::{Event, EventReader, EventWriter}, query::With, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Commands, Query, Res, ResMut, Resource, Single}}, hierarchy::{BuildChildren, ChildBuild, DespawnRecursiveExt}, input::{keyboard::KeyCode, mouse::MouseButton, ButtonInput}, math::Vec3, render::camera::Camera, sprite::{ColorMaterial, MeshMaterial2d, Sprite}, text::{cosmic_text::ttf_parser::{Rect, Style}, FontStyle, JustifyText, TextColor, TextFont, TextLayout}, transform::components::{GlobalTransform, Transform}, ui::{node_bundles::NodeBundle, widget::Text, AlignItems, BackgroundColor, FlexDirection, JustifyContent, JustifyItems, Node, PositionType, RelativeCursorPosition, UiRect, Val}, utils::default, window::{CursorMoved, PrimaryWindow, Window}, winit::WinitSettings, DefaultPlugins};
-use messages::game::{server_message::Message, PlayerMove, PlayerType, ServerMessage};
-use network::socket_plugin::{SocketPlugin, SocketRecv, SocketSend};
-use meta::ui::{self, GameUI, MetaEvent};
+use std::time::Duration;
+
+use bevy::{app::{App, Startup, Update}, asset::{AssetServer, Assets}, color::{Alpha, Color}, core_pipeline::core_2d::Camera2d, ecs::{component::Component, entity::Entity, event::{Event, EventReader, EventWriter}, query::With, schedule::{common_conditions::{on_event, resource_changed, resource_exists}, Condition, IntoSystemConfigs}, system::{Commands, Local, Query, Res, ResMut, Resource, Single}}, hierarchy::{BuildChildren, ChildBuild, DespawnRecursiveExt}, input::{keyboard::{Key, KeyCode, KeyboardInput}, mouse::MouseButton, ButtonInput}, math::{Vec2, Vec3}, render::camera::Camera, sprite::{ColorMaterial, MeshMaterial2d, Sprite}, text::{cosmic_text::ttf_parser::{Rect, Style}, FontStyle, JustifyText, Text2d, TextColor, TextFont, TextLayout}, time::{Time, Timer, TimerMode}, transform::components::{GlobalTransform, Transform}, ui::{node_bundles::NodeBundle, widget::Text, AlignItems, BackgroundColor, FlexDirection, JustifyContent, JustifyItems, Node, PositionType, RelativeCursorPosition, UiRect, Val}, utils::default, window::{CursorMoved, PrimaryWindow, Window}, winit::WinitSettings, DefaultPlugins};
+use game_core::ai::{self, Difficulty};
+use game_core::{Cell, Game, Move, NumericalBoard, Outcome, Piece, Player, UltimateBoard, Variant, SUB_BOARDS};
+use messages::game::{client_message::Message as Request, server_message::Message, ClientMessage, CreateRoom, ErrorCode, FindGame, GameFinished, BotDifficulty, GameOutcome, JoinRoom, PlayBot, PlayerMove, PlayerType, RematchRequest, Rules, Spectate, TimeControl};
+use network::socket_plugin::{SocketLost, SocketPlugin, SocketRecv, SocketSend};
+use meta::lobby::{LobbyChoice, LobbyUI};
+use meta::ui::{self, GameResult, GameUI, MetaEvent};
+use replay::ReplayPlugin;
 use wasm_bindgen::{prelude::{wasm_bindgen, Closure}, JsCast};
 use javascript::bindings::log;
 
 #[derive(Resource, Debug)]
 struct GameState {
-    board: [u8; 9],
-    game_finished: Option<bool>,
+    board: Game,
+    game_finished: Option<GameResult>,
     is_your_turn: bool,
-    me: PlayerType
+    me: Player,
+    mode: GameMode
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GameMode {
+    Online,
+    // Watching someone else's game, so the board takes no input.
+    Spectating,
+    // Two people sharing this screen, no server involved.
+    HotSeat,
+    // Against the engine compiled into this client, also offline.
+    VsComputer { difficulty: Difficulty },
+    // Stepping through a finished game downloaded from the server.
+    Replay
+}
+
+#[derive(Event)]
+struct ComputerMove {
+    mv: Move
+}
+
+/// The computer's pretend thinking time before its next move.
+#[derive(Resource)]
+struct ComputerThinking {
+    timer: Timer
 }
 
+const COMPUTER_THINKING_SECS: f32 = 0.6;
+
 #[derive(Event)]
 struct PlayersMove {
-    cell: usize
+    cell: usize,
+    piece: Piece
+}
+
+/// Numerical only: the number the next click puts down, picked with
+/// the keys 1 to 9.
+#[derive(Resource, Default)]
+struct ChosenNumber(Option<u8>);
+
+/// Result held back for a moment so the last mark and the winning
+/// line are visible before the final modal covers the board.
+#[derive(Resource)]
+struct PendingResult {
+    result: GameResult,
+    // Whether the final modal offers a rematch.
+    rematch: bool,
+    timer: Timer
+}
+
+/// Time left on both clocks as of the last `ClockUpdate`, counted down
+/// here in between. Only there while a clock is running.
+#[derive(Resource)]
+struct Clocks {
+    x: Duration,
+    o: Duration,
+    running: Player
 }
 
+impl Clocks {
+    fn left(&self, player: Player) -> Duration {
+        match player {
+            Player::X => self.x,
+            Player::O => self.o
+        }
+    }
+}
+
+const CLOCK_COLOR: Color = Color::srgb(0.6, 0.6, 0.6);
+const RUNNING_CLOCK_COLOR: Color = Color::srgb(0.941, 0.941, 0.286);
+
 #[wasm_bindgen]
 pub fn start_bevy() {
+    run(true);
+}
+
+/// Starts without a server connection, so only local games are offered.
+#[wasm_bindgen]
+pub fn start_bevy_offline() {
+    run(false);
+}
+
+fn run(online: bool) {
     let game_state = GameState {
-        board: [228;9],
+        board: Game::default(),
         game_finished: None,
         is_your_turn: false,
-        me: PlayerType::O
+        me: Player::O,
+        mode: GameMode::Online
     };
 
-    App::new()
+    let mut app = App::new();
+    if online {
+        app.add_plugins(SocketPlugin);
+    }
+    app
         .insert_resource(game_state)
-        .add_plugins((DefaultPlugins, SocketPlugin, GameUI))
+        .add_plugins((DefaultPlugins, GameUI, LobbyUI { online }, ReplayPlugin))
         .add_systems(Startup, setup)
+        .add_systems(Update, layout_grid)
+        .add_systems(Update, draw_sub_boards.run_if(resource_changed::<GameState>))
+        .add_systems(Update, show_piece_hint.run_if(resource_changed::<GameState>.or(resource_changed::<ChosenNumber>)))
         .add_systems(Update, draw.run_if(on_event::<DrawRequest>))
+        .add_systems(Update, highlight.run_if(on_event::<HighlightLine>))
+        .add_systems(Update, reveal_result.run_if(resource_exists::<PendingResult>))
+        .add_systems(Update, tick_clocks.run_if(resource_exists::<Clocks>))
         .add_systems(Update, (handle_update_from_network.run_if(on_event::<SocketRecv>)))
+        .add_systems(Update, connection_lost.run_if(on_event::<SocketLost>))
         .add_systems(Update, process_players_move.run_if(on_event::<PlayersMove>))
-        .add_systems(Update, input)
+        .add_systems(Update, handle_lobby_choice.run_if(on_event::<LobbyChoice>))
+        .add_systems(Update, computer_think.run_if(resource_exists::<ComputerThinking>))
+        .add_systems(Update, process_computer_move.run_if(on_event::<ComputerMove>))
+        .add_systems(Update, (choose_number, input))
+        .init_resource::<ChosenNumber>()
         .add_event::<PlayersMove>()
+        .add_event::<ComputerMove>()
         .add_event::<DrawRequest>()
+        .add_event::<HighlightLine>()
+        // Registered here too, so the game systems work without SocketPlugin.
+        .add_event::<SocketRecv>()
+        .add_event::<SocketSend>()
+        .add_event::<SocketLost>()
         .run();
 }
 
-fn setup(
+fn setup(mut commands: Commands) {
+    commands.spawn(Camera2d);
+}
+
+// Boards are squeezed into this many pixels, without cells growing past their images.
+const BOARD_MAX_PX: f32 = 600.;
+const CELL_MAX_PX: f32 = 100.;
+const GRID_LINE_PX: f32 = 2.;
+const SUB_BOARD_LINE_PX: f32 = 6.;
+
+const PLAYABLE_COLOR: Color = Color::srgba(0.376, 0.376, 0.820, 0.25);
+
+/// Where the cells of a board for the given variant sit on screen,
+/// centered on the origin.
+#[derive(Clone, Copy)]
+struct BoardLayout {
+    size: usize,
+    cell_px: f32,
+    // Ultimate boards number their cells sub-board by sub-board.
+    nested: bool
+}
+
+impl BoardLayout {
+    fn new(variant: Variant) -> Self {
+        let (size, nested) = match variant {
+            Variant::Standard(rules) => (rules.size(), false),
+            Variant::Ultimate => (SUB_BOARDS, true),
+            Variant::Misere | Variant::Wild | Variant::Numerical => (3, false)
+        };
+        BoardLayout { size, cell_px: (BOARD_MAX_PX / size as f32).min(CELL_MAX_PX), nested }
+    }
+
+    fn width(&self) -> f32 {
+        self.cell_px * self.size as f32
+    }
+
+    fn row_col(&self, cell: usize) -> (usize, usize) {
+        if !self.nested {
+            return (cell / self.size, cell % self.size);
+        }
+        let (sub_board, cell) = UltimateBoard::split(cell);
+        (sub_board / 3 * 3 + cell / 3, sub_board % 3 * 3 + cell % 3)
+    }
+
+    fn cell(&self, row: usize, col: usize) -> usize {
+        if !self.nested {
+            return row * self.size + col;
+        }
+        let (sub_board, cell) = (row / 3 * 3 + col / 3, row % 3 * 3 + col % 3);
+        sub_board * SUB_BOARDS + cell
+    }
+
+    fn cell_translation(&self, cell: usize, z: f32) -> Vec3 {
+        let half = self.width() / 2.;
+        let (row, col) = self.row_col(cell);
+        Vec3::new(-half + self.cell_px * (col as f32 + 0.5), half - self.cell_px * (row as f32 + 0.5), z)
+    }
+
+    /// Center of a sub-board, which is as wide as three cells.
+    fn sub_board_translation(&self, sub_board: usize, z: f32) -> Vec3 {
+        // The middle cell of a sub-board sits right at its center.
+        self.cell_translation(sub_board * SUB_BOARDS + 4, z)
+    }
+
+    fn cell_at(&self, position: Vec2) -> Option<usize> {
+        let half = self.width() / 2.;
+        let (x, y) = (position.x + half, half - position.y);
+        if x < 0. || y < 0. || x >= self.width() || y >= self.width() {
+            return None;
+        }
+        let (row, col) = ((y / self.cell_px) as usize, (x / self.cell_px) as usize);
+        Some(self.cell(row.min(self.size - 1), col.min(self.size - 1)))
+    }
+}
+
+/// The paper and the lines of the board, redrawn whenever the variant changes.
+#[derive(Component)]
+struct Grid;
+
+fn layout_grid(
+    mut commands: Commands,
+    game_state: Res<GameState>,
+    grid: Query<Entity, With<Grid>>,
+    mut drawn: Local<Option<Variant>>
+) {
+    let variant = game_state.board.variant();
+    if *drawn == Some(variant) {
+        return;
+    }
+    *drawn = Some(variant);
+    for part in grid.iter() {
+        commands.entity(part).despawn_recursive();
+    }
+
+    let layout = BoardLayout::new(variant);
+    let width = layout.width();
+    commands.spawn((Grid, Sprite::from_color(Color::WHITE, Vec2::splat(width)), Transform::default()));
+    for i in 0..=layout.size {
+        let offset = -width / 2. + layout.cell_px * i as f32;
+        // Sub-boards stand out from the cells inside them.
+        let thickness = if layout.nested && i % 3 == 0 { SUB_BOARD_LINE_PX } else { GRID_LINE_PX };
+        commands.spawn((
+            Grid,
+            Sprite::from_color(Color::BLACK, Vec2::new(thickness, width)),
+            Transform::from_xyz(offset, 0., 0.1)
+        ));
+        commands.spawn((
+            Grid,
+            Sprite::from_color(Color::BLACK, Vec2::new(width, thickness)),
+            Transform::from_xyz(0., offset, 0.1)
+        ));
+    }
+}
+
+/// Ultimate only: tints the sub-boards the next move may go to and
+/// covers the claimed ones with their owner's mark.
+#[derive(Component)]
+struct SubBoardOverlay;
+
+fn draw_sub_boards(
     mut commands: Commands,
+    game_state: Res<GameState>,
+    overlays: Query<Entity, With<SubBoardOverlay>>,
     asset_server: Res<AssetServer>
 ) {
-    commands.spawn(Camera2d);
+    for overlay in overlays.iter() {
+        commands.entity(overlay).despawn_recursive();
+    }
+    let Game::Ultimate(board) = &game_state.board else {
+        return;
+    };
 
-    commands.spawn((
-        Sprite::from_image(asset_server.load("desk.png")),
-        Transform {
-            translation: Vec3::new(0., 0., 0.),
-            ..default()
+    let layout = BoardLayout::new(Variant::Ultimate);
+    let sub_board_px = Vec2::splat(3. * layout.cell_px);
+    for sub_board in 0..SUB_BOARDS {
+        if let Some(owner) = board.claimed_by(sub_board) {
+            let image_name = match owner {
+                Player::X => "tic.png",
+                Player::O => "tac.png"
+            };
+            commands.spawn((
+                SubBoardOverlay,
+                Sprite {
+                    custom_size: Some(sub_board_px),
+                    color: Color::WHITE.with_alpha(0.85),
+                    ..Sprite::from_image(asset_server.load(image_name))
+                },
+                Transform::from_translation(layout.sub_board_translation(sub_board, 2.))
+            ));
+        } else if game_state.game_finished.is_none() && board.is_playable(sub_board) {
+            commands.spawn((
+                SubBoardOverlay,
+                Sprite::from_color(PLAYABLE_COLOR, sub_board_px),
+                Transform::from_translation(layout.sub_board_translation(sub_board, 0.05))
+            ));
         }
-    ));
+    }
 }
 
 #[derive(Event, Debug)]
 struct DrawRequest {
-    who: PlayerType,
+    piece: Piece,
     where_: usize
 }
 
+/// What to draw for a cell, if anything.
+fn piece_on(cell: Cell) -> Option<Piece> {
+    match cell {
+        Cell::Empty => None,
+        Cell::Taken(player) => Some(Piece::Mark(player)),
+        Cell::Number(number) => Some(Piece::Number(number))
+    }
+}
+
+/// Anything drawn on top of the grid, cleared when the board is rebuilt.
+#[derive(Component)]
+struct Mark;
+
+/// The cell a drawn piece sits on, so a refused move can be taken back.
+#[derive(Component)]
+struct PieceAt(usize);
+
+/// Our last online move, drawn before the server accepted it. Goes once
+/// the game moves on, and is taken back if the server refuses it.
+#[derive(Resource)]
+struct UnconfirmedMove {
+    before: Game,
+    cell: usize
+}
+
+#[derive(Event, Debug)]
+struct HighlightLine {
+    cells: Vec<usize>
+}
+
 fn draw(
     mut commands: Commands,
     mut queue: EventReader<DrawRequest>,
+    game_state: Res<GameState>,
     asset_server: Res<AssetServer>,
 ) {
+    let layout = BoardLayout::new(game_state.board.variant());
     for dr in queue.read() {
         console_log!("draw called {:?}", dr);
-        let cell_index = dr.where_ as i32;
-        let player: &PlayerType = &dr.who;
-
-        let local_origin = (-100, 100);
-        let cell_coordinates = (local_origin.0 + 100 * (cell_index % 3), local_origin.1 - 100*(cell_index / 3));
+        let player = match dr.piece {
+            Piece::Mark(player) => player,
+            Piece::Number(number) => {
+                commands.spawn((
+                    Mark,
+                    PieceAt(dr.where_),
+                    Text2d::new(number.to_string()),
+                    TextFont { font_size: layout.cell_px * 0.6, ..default() },
+                    TextColor(Color::BLACK),
+                    Transform::from_translation(layout.cell_translation(dr.where_, 1.))
+                ));
+                continue;
+            }
+        };
 
         let image_name = match player {
-            PlayerType::X => "tic.png",
-            PlayerType::O => "tac.png"
+            Player::X => "tic.png",
+            Player::O => "tac.png"
         };
 
         commands.spawn((
-            Sprite::from_image(asset_server.load(image_name)),
+            Mark,
+            PieceAt(dr.where_),
+            Sprite {
+                custom_size: Some(Vec2::splat(layout.cell_px)),
+                ..Sprite::from_image(asset_server.load(image_name))
+            },
             Transform {
-                translation: Vec3::new(cell_coordinates.0 as f32, cell_coordinates.1 as f32, 1.),
+                translation: layout.cell_translation(dr.where_, 1.),
                 ..default()
             }
         ));
     }
 }
 
+fn highlight(
+    mut commands: Commands,
+    game_state: Res<GameState>,
+    mut queue: EventReader<HighlightLine>
+) {
+    let layout = BoardLayout::new(game_state.board.variant());
+    for line in queue.read() {
+        for cell in &line.cells {
+            // Ultimate games are won by a line of whole sub-boards.
+            let (translation, size) = if layout.nested {
+                (layout.sub_board_translation(*cell, 0.5), Vec2::splat(3. * layout.cell_px))
+            } else {
+                (layout.cell_translation(*cell, 0.5), Vec2::splat(layout.cell_px))
+            };
+            commands.spawn((
+                Mark,
+                Sprite::from_color(Color::srgb(0.92, 0.92, 0.247).with_alpha(0.6), size),
+                Transform {
+                    translation,
+                    ..default()
+                }
+            ));
+        }
+    }
+}
+
+fn reveal_result(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pending: ResMut<PendingResult>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    if pending.timer.tick(time.delta()).just_finished() {
+        meta_event.send(MetaEvent::GameFinished { result: pending.result, rematch: pending.rematch });
+        commands.remove_resource::<PendingResult>();
+    }
+}
+
 fn handle_update_from_network(
+    mut commands: Commands,
+    marks: Query<Entity, With<Mark>>,
+    pieces: Query<(Entity, &PieceAt)>,
+    unconfirmed: Option<Res<UnconfirmedMove>>,
     mut game_state: ResMut<GameState>,
     mut ev_message: EventReader<SocketRecv>,
     mut draw_queue: EventWriter<DrawRequest>,
+    mut highlight_queue: EventWriter<HighlightLine>,
     mut meta_event: EventWriter<MetaEvent>
 ) {
     for SocketRecv(ev) in ev_message.read() {
         console_log!("receive network update event");
-        if let Some(message) = ev.message {
+        if let Some(message) = &ev.message {
+            // Anything that moves the game on means our move went through.
+            if matches!(message, Message::InitGame(_) | Message::PlayerMove(_) | Message::GameFinished(_) | Message::GameSnapshot(_)) {
+                commands.remove_resource::<UnconfirmedMove>();
+            }
             match message {
-                Message::InitGame(g) => {                    
-                    if let Ok(PlayerType::X) = PlayerType::try_from(g.your_player) {
-                        game_state.is_your_turn = true;
-                        game_state.me = PlayerType::X;
+                Message::InitGame(g) => {
+                    let Some(variant) = messages::decode_variant(g.rules.as_ref()) else {
+                        console_log!("server started a game with rules we can't play: {:?}", g.rules);
+                        continue;
+                    };
+                    for mark in marks.iter() {
+                        commands.entity(mark).despawn_recursive();
                     }
+                    commands.remove_resource::<Clocks>();
+                    // A rematch can start before the last result was shown.
+                    commands.remove_resource::<PendingResult>();
+                    let me = PlayerType::try_from(g.your_player).unwrap_or(PlayerType::X).into();
+                    *game_state = GameState {
+                        board: Game::new(variant),
+                        game_finished: None,
+                        is_your_turn: me == Player::X,
+                        me,
+                        mode: GameMode::Online
+                    };
                     meta_event.send(MetaEvent::OpponentFound);
-                    console_log!("got init game: {:?}; {:?}", game_state, g.your_player);
+                    console_log!("got init game {}: {:?}; {:?}", g.game_id, game_state, g.your_player);
                 }
                 Message::GameFinished(f) => {
-                    game_state.game_finished = Some(f.winner);
-                    meta_event.send(MetaEvent::GameFinished(f.winner));
+                    // Draw whatever the final board has that ours doesn't yet,
+                    // which is the finishing move when the opponent made it.
+                    if let Some(final_board) = messages::decode_board(game_state.board.variant(), &f.board, None) {
+                        let cells = game_state.board.cells().iter().zip(final_board.cells());
+                        for (cell, (mine, final_cell)) in cells.enumerate() {
+                            if let (Cell::Empty, Some(piece)) = (mine, piece_on(*final_cell)) {
+                                draw_queue.send(DrawRequest { piece, where_: cell });
+                            }
+                        }
+                        game_state.board = final_board;
+                    }
+                    if !f.winning_line.is_empty() {
+                        highlight_queue.send(HighlightLine { cells: f.winning_line.iter().map(|cell| *cell as usize).collect() });
+                    }
+
+                    let result = game_result(&game_state, f);
+                    // Whoever left isn't there to ask, and spectators can't.
+                    let rematch = game_state.mode == GameMode::Online && f.outcome() != GameOutcome::Abandoned;
+                    finish_game(&mut commands, &mut game_state, result, rematch);
+                }
+                Message::GameSnapshot(snapshot) => {
+                    let board = messages::decode_variant(snapshot.rules.as_ref())
+                        .and_then(|variant| messages::decode_board(variant, &snapshot.board, snapshot.forced_board));
+                    let Some(board) = board else {
+                        console_log!("server sent a snapshot with a malformed board");
+                        continue;
+                    };
+                    for mark in marks.iter() {
+                        commands.entity(mark).despawn_recursive();
+                    }
+                    // The clocks follow right after, if the game has any.
+                    commands.remove_resource::<Clocks>();
+                    for (cell, state) in board.cells().iter().enumerate() {
+                        if let Some(piece) = piece_on(*state) {
+                            draw_queue.send(DrawRequest { piece, where_: cell });
+                        }
+                    }
+
+                    game_state.board = board;
+                    if snapshot.spectating {
+                        game_state.mode = GameMode::Spectating;
+                        game_state.is_your_turn = false;
+                        meta_event.send(MetaEvent::Spectating { x_label: snapshot.x_label.clone(), o_label: snapshot.o_label.clone() });
+                    } else {
+                        game_state.mode = GameMode::Online;
+                        game_state.me = snapshot.your_player().into();
+                        game_state.is_your_turn = snapshot.turn() == snapshot.your_player();
+                        meta_event.send(MetaEvent::OpponentFound);
+                    }
+                    console_log!("rebuilt game from snapshot: {:?}", game_state);
+                }
+                Message::ClockUpdate(update) => {
+                    if game_state.game_finished.is_none() {
+                        commands.insert_resource(Clocks {
+                            x: Duration::from_millis(update.x_remaining_ms.into()),
+                            o: Duration::from_millis(update.o_remaining_ms.into()),
+                            running: update.running().into()
+                        });
+                    }
+                }
+                Message::OpponentLeft(left) => {
+                    meta_event.send(MetaEvent::OpponentLeft(left.forfeit_in_secs));
+                }
+                Message::OpponentReturned(_) => {
+                    meta_event.send(MetaEvent::OpponentReturned);
+                }
+                Message::RoomCreated(room) => {
+                    meta_event.send(MetaEvent::RoomCreated(room.code.clone()));
+                }
+                Message::RoomNotFound(room) => {
+                    meta_event.send(MetaEvent::RoomNotFound(room.code.clone()));
+                }
+                Message::GameNotFound(game) => {
+                    meta_event.send(MetaEvent::GameNotFound(game.game_id));
+                }
+                Message::RematchRequest(_) => {
+                    meta_event.send(MetaEvent::RematchRequested);
+                }
+                Message::RematchAccepted(series) => {
+                    meta_event.send(MetaEvent::RematchAccepted { wins: series.wins, losses: series.losses, draws: series.draws });
+                }
+                Message::SearchStatus(status) => {
+                    meta_event.send(MetaEvent::Searching { rating: status.rating, window: status.window });
+                }
+                Message::RatingChange(change) => {
+                    meta_event.send(MetaEvent::RatingChanged { rating: change.rating, delta: change.delta });
                 }
                 Message::PlayerMove(mv) => {
-                    let cell = mv.cell as usize;
-                    game_state.board[cell] = 96;
-                    game_state.is_your_turn = true;
-                    draw_queue.send(DrawRequest { who: game_state.me.opposite(), where_: cell });
+                    // Spectators see both sides move, so go by whose turn it is.
+                    let spectating = game_state.mode == GameMode::Spectating;
+                    let mover = if spectating { game_state.board.turn() } else { game_state.me.opposite() };
+                    let Some(opponent_move) = messages::decode_move(mv, mover) else {
+                        console_log!("server sent a malformed move: {:?}", mv);
+                        continue;
+                    };
+                    if let Err(err) = game_state.board.apply_move(opponent_move) {
+                        console_log!("server sent a move our board rejects: {:?}", err);
+                        continue;
+                    }
+                    game_state.is_your_turn = !spectating;
+                    draw_queue.send(DrawRequest { piece: opponent_move.piece, where_: opponent_move.cell });
+                }
+                Message::Welcome(welcome) => {
+                    console_log!("server speaks version {} with {:?}", welcome.protocol_version, welcome.capabilities);
+                }
+                Message::Error(error) => {
+                    console_log!("server refused what we sent: {:?}", error);
+                    // Only moves are drawn before the server answers, so
+                    // that's all there is to take back, and only if it's the
+                    // move the server names. Connection-level errors don't
+                    // name one and can arrive after our move was accepted.
+                    let refused_cell = error.rejected_move.as_ref()
+                        .and_then(|mv| messages::decode_move(mv, game_state.me))
+                        .map(|mv| mv.cell);
+                    if let Some(unconfirmed) = unconfirmed.as_ref().filter(|unconfirmed| refused_cell == Some(unconfirmed.cell)) {
+                        for (piece, at) in pieces.iter() {
+                            if at.0 == unconfirmed.cell {
+                                commands.entity(piece).despawn_recursive();
+                            }
+                        }
+                        game_state.board = unconfirmed.before;
+                        game_state.is_your_turn = true;
+                        commands.remove_resource::<UnconfirmedMove>();
+                    }
+                    meta_event.send(MetaEvent::Refused(refusal_text(error.code()).to_string()));
+                }
+                Message::HelloRejected(rejected) => {
+                    console_log!("server speaks versions {} to {} only", rejected.min_protocol_version, rejected.protocol_version);
+                    meta_event.send(MetaEvent::Rejected(rejected.reason.clone()));
                 }
             }
         }
     }
 }
 
+fn connection_lost(
+    mut ev_lost: EventReader<SocketLost>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    ev_lost.clear();
+    meta_event.send(MetaEvent::ConnectionLost);
+}
+
+fn refusal_text(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::NotYourTurn => "It's not your turn yet",
+        ErrorCode::CellOccupied => "That cell is already taken",
+        ErrorCode::GameOver => "The game is already over",
+        ErrorCode::RateLimited => "Slow down, the server is dropping messages",
+        ErrorCode::IllegalMove => "That move isn't allowed here",
+        ErrorCode::MalformedMessage => "The server couldn't make sense of that"
+    }
+}
+
+fn game_result(game_state: &GameState, finished: &GameFinished) -> GameResult {
+    let winner = match finished.outcome() {
+        GameOutcome::Draw => None,
+        GameOutcome::XWins => Some(Player::X),
+        GameOutcome::OWins => Some(Player::O),
+        GameOutcome::Abandoned | GameOutcome::Timeout => Some(finished.winner().into())
+    };
+
+    match winner {
+        _ if game_state.mode == GameMode::Spectating => GameResult::Winner(winner),
+        None => GameResult::Draw,
+        Some(winner) if winner == game_state.me => GameResult::Won,
+        Some(_) => GameResult::Lost
+    }
+}
+
+fn finish_game(commands: &mut Commands, game_state: &mut GameState, result: GameResult, rematch: bool) {
+    // The clocks stay on screen, stopped where they were.
+    commands.remove_resource::<Clocks>();
+    game_state.game_finished = Some(result);
+    game_state.is_your_turn = false;
+    commands.insert_resource(PendingResult { result, rematch, timer: Timer::from_seconds(1.5, TimerMode::Once) });
+}
+
+fn handle_lobby_choice(
+    mut commands: Commands,
+    marks: Query<Entity, With<Mark>>,
+    mut game_state: ResMut<GameState>,
+    mut ev_choice: EventReader<LobbyChoice>,
+    mut ev_message: EventWriter<SocketSend>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    for choice in ev_choice.read() {
+        let message = match choice {
+            LobbyChoice::HotSeat(variant) => {
+                start_local_game(&mut commands, &marks, &mut game_state, *variant, GameMode::HotSeat, Player::X);
+                meta_event.send(MetaEvent::OpponentFound);
+                continue;
+            },
+            LobbyChoice::LocalComputer { difficulty, me, variant } => {
+                start_local_game(&mut commands, &marks, &mut game_state, *variant, GameMode::VsComputer { difficulty: *difficulty }, *me);
+                if *me == Player::O {
+                    commands.insert_resource(ComputerThinking { timer: Timer::from_seconds(COMPUTER_THINKING_SECS, TimerMode::Once) });
+                }
+                meta_event.send(MetaEvent::OpponentFound);
+                continue;
+            },
+            LobbyChoice::Rematch => {
+                let (variant, me) = (game_state.board.variant(), game_state.me);
+                match game_state.mode {
+                    GameMode::Online | GameMode::Spectating => Request::RematchRequest(RematchRequest {}),
+                    GameMode::Replay => continue,
+                    GameMode::HotSeat => {
+                        start_local_game(&mut commands, &marks, &mut game_state, variant, GameMode::HotSeat, Player::X);
+                        meta_event.send(MetaEvent::OpponentFound);
+                        continue;
+                    },
+                    // Sides swap, like they do online.
+                    GameMode::VsComputer { difficulty } => {
+                        start_local_game(&mut commands, &marks, &mut game_state, variant, GameMode::VsComputer { difficulty }, me.opposite());
+                        if me.opposite() == Player::O {
+                            commands.insert_resource(ComputerThinking { timer: Timer::from_seconds(COMPUTER_THINKING_SECS, TimerMode::Once) });
+                        }
+                        meta_event.send(MetaEvent::OpponentFound);
+                        continue;
+                    }
+                }
+            },
+            LobbyChoice::QuickMatch(variant, time_control) => Request::FindGame(FindGame { rules: Some(rules(*variant, time_control)) }),
+            LobbyChoice::CreateRoom(variant, time_control) => Request::CreateRoom(CreateRoom { rules: Some(rules(*variant, time_control)) }),
+            // Replays are downloaded rather than played over the socket.
+            LobbyChoice::WatchReplay(_) => continue,
+            LobbyChoice::JoinRoom(code) => Request::JoinRoom(JoinRoom { code: code.clone() }),
+            LobbyChoice::Spectate(game_id) => Request::Spectate(Spectate { game_id: *game_id }),
+            LobbyChoice::PlayBot(difficulty, variant, time_control) => Request::PlayBot(PlayBot {
+                difficulty: BotDifficulty::from(*difficulty) as i32,
+                rules: Some(rules(*variant, time_control))
+            })
+        };
+        ev_message.send(SocketSend(ClientMessage { seq: 0, message: Some(message) }));
+    }
+}
+
+fn rules(variant: Variant, time_control: &Option<TimeControl>) -> Rules {
+    Rules { time_control: time_control.clone(), ..variant.into() }
+}
+
+fn start_local_game(
+    commands: &mut Commands,
+    marks: &Query<Entity, With<Mark>>,
+    game_state: &mut GameState,
+    variant: Variant,
+    mode: GameMode,
+    me: Player
+) {
+    for mark in marks.iter() {
+        commands.entity(mark).despawn_recursive();
+    }
+    commands.remove_resource::<Clocks>();
+    *game_state = GameState {
+        board: Game::new(variant),
+        game_finished: None,
+        is_your_turn: me == Player::X,
+        me,
+        mode
+    };
+}
+
 fn process_players_move(
+    mut commands: Commands,
     mut game_state: ResMut<GameState>,
     mut ev_move: EventReader<PlayersMove>,
     mut ev_message: EventWriter<SocketSend>,
-    mut draw_queue: EventWriter<DrawRequest>
+    mut draw_queue: EventWriter<DrawRequest>,
+    mut highlight_queue: EventWriter<HighlightLine>
 ) {
     if !game_state.is_your_turn {
         return;
     }
 
     for player_move in ev_move.read() {
-        if game_state.board[player_move.cell] != 228 {
+        let before = game_state.board;
+        let mv = Move { cell: player_move.cell, player: mover(&game_state), piece: player_move.piece };
+        let Ok(outcome) = game_state.board.apply_move(mv) else {
             return;
+        };
+        draw_queue.send(DrawRequest { piece: mv.piece, where_: mv.cell });
+
+        match game_state.mode {
+            GameMode::Replay => return,
+            GameMode::Online | GameMode::Spectating => {
+                let player_move = messages::encode_move(game_state.board.variant(), mv);
+                // Numbered, so a move sent twice is only played once.
+                let seq = game_state.board.moves_played() as u32;
+                ev_message.send(SocketSend(ClientMessage{seq, message: Some(Request::PlayerMove(player_move))}));
+                commands.insert_resource(UnconfirmedMove { before, cell: mv.cell });
+                game_state.is_your_turn = false;
+            },
+            GameMode::HotSeat => {
+                if let Some(outcome) = outcome {
+                    finish_local_game(&mut commands, &mut game_state, outcome, &mut highlight_queue);
+                    return;
+                }
+            },
+            GameMode::VsComputer { .. } => {
+                if let Some(outcome) = outcome {
+                    finish_local_game(&mut commands, &mut game_state, outcome, &mut highlight_queue);
+                } else {
+                    game_state.is_your_turn = false;
+                    commands.insert_resource(ComputerThinking { timer: Timer::from_seconds(COMPUTER_THINKING_SECS, TimerMode::Once) });
+                }
+                return;
+            }
         }
+    }
+}
+
+fn computer_think(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut thinking: ResMut<ComputerThinking>,
+    game_state: Res<GameState>,
+    mut ev_move: EventWriter<ComputerMove>
+) {
+    if !thinking.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    commands.remove_resource::<ComputerThinking>();
 
-        game_state.board[player_move.cell] = 69;
-        ev_message.send(SocketSend(ServerMessage{message: Some(Message::PlayerMove(PlayerMove {cell: player_move.cell as u32}))}));
-        game_state.is_your_turn = false;
-        draw_queue.send(DrawRequest { who: game_state.me, where_: player_move.cell });
+    let GameMode::VsComputer { difficulty } = game_state.mode else {
+        return;
+    };
+    let pick = &mut |n: usize| (js_sys::Math::random() * n as f64) as usize;
+    if let Some(mv) = ai::choose_move(&game_state.board, difficulty, pick) {
+        ev_move.send(ComputerMove { mv });
     }
 }
 
+fn process_computer_move(
+    mut commands: Commands,
+    mut game_state: ResMut<GameState>,
+    mut ev_move: EventReader<ComputerMove>,
+    mut draw_queue: EventWriter<DrawRequest>,
+    mut highlight_queue: EventWriter<HighlightLine>
+) {
+    for computer_move in ev_move.read() {
+        let mv = computer_move.mv;
+        if mv.player != game_state.me.opposite() {
+            continue;
+        }
+        let Ok(outcome) = game_state.board.apply_move(mv) else {
+            continue;
+        };
+        draw_queue.send(DrawRequest { piece: mv.piece, where_: mv.cell });
+
+        match outcome {
+            Some(outcome) => finish_local_game(&mut commands, &mut game_state, outcome, &mut highlight_queue),
+            None => game_state.is_your_turn = true
+        }
+    }
+}
+
+/// Nobody else keeps score in local games, so the client decides when it's over.
+fn finish_local_game(
+    commands: &mut Commands,
+    game_state: &mut GameState,
+    outcome: Outcome,
+    highlight_queue: &mut EventWriter<HighlightLine>
+) {
+    if let Some((_, line)) = game_state.board.winning_line() {
+        highlight_queue.send(HighlightLine { cells: line.cells().to_vec() });
+    }
+    let result = match (game_state.mode, outcome) {
+        (GameMode::HotSeat, Outcome::Win(player)) => GameResult::Winner(Some(player)),
+        (GameMode::HotSeat, Outcome::Draw) => GameResult::Winner(None),
+        (_, Outcome::Draw) => GameResult::Draw,
+        (_, Outcome::Win(player)) if player == game_state.me => GameResult::Won,
+        (_, Outcome::Win(_)) => GameResult::Lost
+    };
+    finish_game(commands, game_state, result, true);
+}
+
+/// One side's remaining time, drawn above the board.
+#[derive(Component)]
+struct ClockLabel(Player);
+
+fn tick_clocks(
+    mut commands: Commands,
+    time: Res<Time>,
+    game_state: Res<GameState>,
+    mut clocks: ResMut<Clocks>,
+    mut labels: Query<(&ClockLabel, &mut Text2d, &mut TextColor)>
+) {
+    let running = clocks.running;
+    let left = match running {
+        Player::X => &mut clocks.x,
+        Player::O => &mut clocks.o
+    };
+    *left = left.saturating_sub(time.delta());
+
+    if labels.is_empty() {
+        let half = BoardLayout::new(game_state.board.variant()).width() / 2.;
+        for (player, x) in [(Player::X, -half / 2.), (Player::O, half / 2.)] {
+            commands.spawn((
+                Mark,
+                ClockLabel(player),
+                Text2d::new(clock_text(player, clocks.left(player))),
+                TextFont { font_size: 24., ..default() },
+                TextColor(CLOCK_COLOR),
+                Transform::from_xyz(x, half + 24., 1.)
+            ));
+        }
+        return;
+    }
+    for (label, mut text, mut color) in labels.iter_mut() {
+        text.0 = clock_text(label.0, clocks.left(label.0));
+        color.0 = if label.0 == running { RUNNING_CLOCK_COLOR } else { CLOCK_COLOR };
+    }
+}
+
+/// Minutes and seconds, and tenths once it gets tight.
+fn clock_text(player: Player, left: Duration) -> String {
+    let secs = left.as_secs();
+    if secs < 10 {
+        format!("{player:?} {secs}.{}", left.subsec_millis() / 100)
+    } else {
+        format!("{player:?} {}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Who the clicks on this screen play for. In hot-seat games both sides
+/// play from this screen.
+fn mover(game_state: &GameState) -> Player {
+    match game_state.mode {
+        GameMode::HotSeat => game_state.board.turn(),
+        _ => game_state.me
+    }
+}
+
+/// The number `player` puts down next in a numerical game: the picked
+/// one while they can still use it, otherwise their lowest unused one.
+fn number_to_play(board: &NumericalBoard, player: Player, chosen: Option<u8>) -> Option<u8> {
+    let usable = |number: &u8| NumericalBoard::owner(*number) == player && !board.is_used(*number);
+    chosen.filter(usable).or_else(|| (1..=9).find(usable))
+}
+
+fn choose_number(
+    mut chosen: ResMut<ChosenNumber>,
+    mut keys: EventReader<KeyboardInput>
+) {
+    for key in keys.read() {
+        if let Key::Character(typed) = &key.logical_key {
+            if let Ok(number @ 1..=9) = typed.parse::<u8>() {
+                chosen.0 = Some(number);
+            }
+        }
+    }
+}
+
+/// Reminds the players how pieces are picked, for the variants where
+/// that isn't just a click.
+#[derive(Component)]
+struct PieceHint;
+
+fn show_piece_hint(
+    mut commands: Commands,
+    game_state: Res<GameState>,
+    chosen: Res<ChosenNumber>,
+    hints: Query<Entity, With<PieceHint>>
+) {
+    for hint in hints.iter() {
+        commands.entity(hint).despawn_recursive();
+    }
+    if matches!(game_state.mode, GameMode::Spectating | GameMode::Replay) || game_state.game_finished.is_some() {
+        return;
+    }
+    let hint = match &game_state.board {
+        Game::Misere(_) => String::from("Misère: whoever completes three in a row loses"),
+        Game::Wild(_) => String::from("Wild: left click puts down X, right click O"),
+        Game::Numerical(board) => match number_to_play(board, mover(&game_state), chosen.0) {
+            Some(number) => format!("Numerical: next click puts down {number}, keys 1 to 9 pick another"),
+            None => String::from("Numerical: lines adding up to 15 win")
+        },
+        Game::Standard(_) | Game::Ultimate(_) => return
+    };
+    let offset = BoardLayout::new(game_state.board.variant()).width() / 2. + 20.;
+    commands.spawn((
+        PieceHint,
+        Text2d::new(hint),
+        TextFont { font_size: 18., ..default() },
+        TextColor(Color::WHITE),
+        Transform::from_xyz(0., -offset, 1.)
+    ));
+}
+
 fn input(
+    game_state: Res<GameState>,
+    chosen: Res<ChosenNumber>,
     buttons: Res<ButtonInput<MouseButton>>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform)>,
     mut ev_message: EventWriter<PlayersMove>,
 ) {
+    if matches!(game_state.mode, GameMode::Spectating | GameMode::Replay) {
+        return;
+    }
+
     let (camera, camera_transform) = q_camera.single();
     let window = q_window.single();
 
@@ -161,36 +974,26 @@ fn input(
         .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
         .map(|ray| ray.origin.truncate());
 
-    if let None = mouse_pos {
+    let Some(cell) = mouse_pos.and_then(|position| BoardLayout::new(game_state.board.variant()).cell_at(position)) else {
         return;
-    }
-
-    let desk_origin = (-150., 150.0);
-    let mouse_pos = mouse_pos.unwrap();
-    let local_x = mouse_pos[0] - desk_origin.0;
-    let local_y = -1. * (mouse_pos[1] - desk_origin.1);
+    };
 
-    if local_x < 0. || local_y < 0. || local_x >= 300. || local_y >= 300. {
+    // Only wild games let players put down the other side's mark.
+    let mover = mover(&game_state);
+    let mark = if buttons.just_pressed(MouseButton::Left) {
+        mover
+    } else if matches!(game_state.board, Game::Wild(_)) && buttons.just_pressed(MouseButton::Right) {
+        mover.opposite()
+    } else {
         return;
-    }
-
-    if buttons.just_pressed(MouseButton::Left) {
-        let row_i = (local_y as i32) / 100;
-        let col_i = (local_x as i32) / 100;
-        ev_message.send(PlayersMove { cell: (3*row_i + col_i) as usize });
-    }
-}
-
-
-trait OppositeExt {
-    fn opposite(self) -> Self;
+    };
+    let piece = match &game_state.board {
+        Game::Numerical(board) => match number_to_play(board, mover, chosen.0) {
+            Some(number) => Piece::Number(number),
+            None => return
+        },
+        _ => Piece::Mark(mark)
+    };
+    ev_message.send(PlayersMove { cell, piece });
 }
 
-impl OppositeExt for PlayerType {
-    fn opposite(self) -> Self {
-        match self {
-            PlayerType::X => PlayerType::O,
-            PlayerType::O => PlayerType::X
-        }
-    }
-} 
\ No newline at end of file
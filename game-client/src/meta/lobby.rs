@@ -0,0 +1,492 @@
+use bevy::{app::{Plugin, Startup, Update}, color::Color, ecs::{component::Component, entity::Entity, event::{Event, EventReader, EventWriter}, query::{Changed, With, Without}, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Commands, Query, Res, ResMut, Resource}}, hierarchy::{BuildChildren, ChildBuild, ChildBuilder, DespawnRecursiveExt}, input::{keyboard::{Key, KeyboardInput}, ButtonState}, text::{JustifyText, TextColor, TextLayout}, ui::{widget::{Button, Text}, AlignItems, BackgroundColor, FlexDirection, Interaction, JustifyContent, Node, UiRect, Val}, utils::default};
+
+use game_core::ai::Difficulty;
+use game_core::{Player, Rules, Variant};
+use messages::game::{time_control::Limit, ClockBank, TimeControl};
+
+use crate::javascript::bindings::{copyToClipboard, inviteCodeFromUrl, inviteLink, replayIdFromUrl, spectateIdFromUrl};
+use crate::meta::ui::{MetaEvent, ShowLeaderboard};
+
+const ROOM_CODE_LEN: usize = 5;
+
+pub(crate) const VARIANT_COUNT: usize = 7;
+const CLOCK_COUNT: usize = 3;
+
+const PANEL_COLOR: Color = Color::srgb(0.376, 0.376, 0.820);
+const TEXT_COLOR: Color = Color::srgb(0.941, 0.941, 0.286);
+pub(crate) const BUTTON_COLOR: Color = Color::srgb(0.157, 0.094, 0.647);
+pub(crate) const BUTTON_HOVER_COLOR: Color = Color::srgb(0.25, 0.18, 0.78);
+
+/// Start menu and private rooms: everything that happens before the
+/// server seats us in a game. Without a server only local games are offered.
+pub struct LobbyUI {
+    pub online: bool
+}
+
+#[derive(Resource)]
+struct LobbyOptions {
+    online: bool,
+    // Index of the variant in `offered_variant`.
+    variant: usize,
+    // Index of the time control in `offered_clock`.
+    clock: usize
+}
+
+impl LobbyOptions {
+    fn variant(&self) -> Variant {
+        offered_variant(self.variant).1
+    }
+
+    fn time_control(&self) -> Option<TimeControl> {
+        offered_clock(self.clock).1
+    }
+}
+
+/// Name and rules of the variants on offer, classic first.
+pub(crate) fn offered_variant(index: usize) -> (&'static str, Variant) {
+    let standard = |size, win_length| Variant::Standard(Rules::new(size, win_length).unwrap_or_default());
+    match index {
+        1 => ("4x4, four in a row", standard(4, 4)),
+        2 => ("15x15, five in a row", standard(15, 5)),
+        3 => ("Ultimate, 9 boards in one", Variant::Ultimate),
+        4 => ("Misère, three in a row loses", Variant::Misere),
+        5 => ("Wild, play X or O", Variant::Wild),
+        6 => ("Numerical, lines of 15", Variant::Numerical),
+        _ => ("Classic 3x3", Variant::default())
+    }
+}
+
+/// Name and limits of the time controls on offer for online games, none first.
+fn offered_clock(index: usize) -> (&'static str, Option<TimeControl>) {
+    let limit = |limit| Some(TimeControl { limit: Some(limit) });
+    match index {
+        1 => ("10s per move", limit(Limit::PerMoveSecs(10))),
+        2 => ("3 min + 2s", limit(Limit::Bank(ClockBank { initial_secs: 180, increment_secs: 2 }))),
+        _ => ("None", None)
+    }
+}
+
+impl Plugin for LobbyUI {
+    fn build(&self, app: &mut bevy::app::App) {
+        app
+            .insert_resource(LobbyOptions { online: self.online, variant: 0, clock: 0 })
+            .add_event::<LobbyChoice>()
+            .add_systems(Startup, open_lobby)
+            .add_systems(Update, (lobby_buttons, code_typing))
+            .add_systems(Update, lobby_processor.run_if(on_event::<MetaEvent>))
+        ;
+    }
+}
+
+/// What the player picked in the lobby, for the game to start locally
+/// or pass on to the server.
+#[derive(Event, Debug)]
+pub enum LobbyChoice {
+    QuickMatch(Variant, Option<TimeControl>),
+    CreateRoom(Variant, Option<TimeControl>),
+    // Whoever opened the room already picked the variant and clock.
+    JoinRoom(String),
+    Spectate(u64),
+    PlayBot(Difficulty, Variant, Option<TimeControl>),
+    HotSeat(Variant),
+    // Offline, against the engine built into the client.
+    LocalComputer { difficulty: Difficulty, me: Player, variant: Variant },
+    // Same opponent and rules again, from the final modal.
+    Rematch,
+    // Play back a finished game, from a replay link.
+    WatchReplay(u64)
+}
+
+#[derive(Component)]
+struct LobbyModal;
+
+#[derive(Component, Clone)]
+enum LobbyButton {
+    NextVariant,
+    NextClock,
+    QuickMatch,
+    CreateRoom,
+    EnterCode,
+    ChooseBot,
+    PlayBot(Difficulty),
+    LocalComputer(Difficulty, Player),
+    HotSeat,
+    Join,
+    Leaderboard,
+    Back,
+    CopyLink(String)
+}
+
+#[derive(Component)]
+struct CodeInput(String);
+
+#[derive(Component)]
+struct CodeStatus;
+
+// Our rating and the range we'd accept, under "Searching opponent...".
+#[derive(Component)]
+struct SearchProgress;
+
+fn open_lobby(
+    mut commands: Commands,
+    options: Res<LobbyOptions>,
+    mut choice: EventWriter<LobbyChoice>
+) {
+    // Opened through an invite, spectator or replay link, so skip the menu.
+    if let Some(game_id) = replayIdFromUrl().and_then(|id| id.parse().ok()) {
+        draw_message_modal(&mut commands, format!("Loading replay of game {game_id}..."));
+        choice.send(LobbyChoice::WatchReplay(game_id));
+    } else if !options.online {
+        draw_main_menu(&mut commands, &options);
+    } else if let Some(game_id) = spectateIdFromUrl().and_then(|id| id.parse().ok()) {
+        draw_message_modal(&mut commands, format!("Joining game {game_id} as a spectator..."));
+        choice.send(LobbyChoice::Spectate(game_id));
+    } else if let Some(code) = inviteCodeFromUrl() {
+        draw_message_modal(&mut commands, format!("Joining room {code}..."));
+        choice.send(LobbyChoice::JoinRoom(code));
+    } else {
+        draw_main_menu(&mut commands, &options);
+    }
+}
+
+fn lobby_buttons(
+    mut commands: Commands,
+    mut options: ResMut<LobbyOptions>,
+    modals: Query<Entity, With<LobbyModal>>,
+    mut buttons: Query<(&Interaction, &LobbyButton, &mut BackgroundColor), Changed<Interaction>>,
+    code_input: Query<&CodeInput>,
+    mut code_status: Query<&mut Text, With<CodeStatus>>,
+    mut choice: EventWriter<LobbyChoice>,
+    mut leaderboard: EventWriter<ShowLeaderboard>
+) {
+    for (interaction, button, mut background) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => *background = BackgroundColor(BUTTON_HOVER_COLOR),
+            Interaction::None => *background = BackgroundColor(BUTTON_COLOR),
+            Interaction::Pressed => {
+                match button {
+                    LobbyButton::NextVariant => {
+                        options.variant = (options.variant + 1) % VARIANT_COUNT;
+                        close_lobby(&mut commands, &modals);
+                        draw_main_menu(&mut commands, &options);
+                    },
+                    LobbyButton::NextClock => {
+                        options.clock = (options.clock + 1) % CLOCK_COUNT;
+                        close_lobby(&mut commands, &modals);
+                        draw_main_menu(&mut commands, &options);
+                    },
+                    LobbyButton::QuickMatch => {
+                        close_lobby(&mut commands, &modals);
+                        draw_search_modal(&mut commands);
+                        choice.send(LobbyChoice::QuickMatch(options.variant(), options.time_control()));
+                    },
+                    LobbyButton::CreateRoom => {
+                        close_lobby(&mut commands, &modals);
+                        draw_message_modal(&mut commands, String::from("Opening a room..."));
+                        choice.send(LobbyChoice::CreateRoom(options.variant(), options.time_control()));
+                    },
+                    LobbyButton::ChooseBot => {
+                        close_lobby(&mut commands, &modals);
+                        draw_bot_menu(&mut commands);
+                    },
+                    LobbyButton::HotSeat => {
+                        close_lobby(&mut commands, &modals);
+                        choice.send(LobbyChoice::HotSeat(options.variant()));
+                    },
+                    LobbyButton::PlayBot(difficulty) => {
+                        close_lobby(&mut commands, &modals);
+                        if options.online {
+                            draw_message_modal(&mut commands, String::from("Starting the game..."));
+                            choice.send(LobbyChoice::PlayBot(*difficulty, options.variant(), options.time_control()));
+                        } else {
+                            draw_side_menu(&mut commands, *difficulty);
+                        }
+                    },
+                    LobbyButton::LocalComputer(difficulty, me) => {
+                        close_lobby(&mut commands, &modals);
+                        choice.send(LobbyChoice::LocalComputer { difficulty: *difficulty, me: *me, variant: options.variant() });
+                    },
+                    LobbyButton::EnterCode => {
+                        close_lobby(&mut commands, &modals);
+                        draw_code_entry(&mut commands, "");
+                    },
+                    LobbyButton::Join => {
+                        if let Ok(CodeInput(code)) = code_input.get_single() {
+                            submit_code(code, &mut code_status, &mut choice);
+                        }
+                    },
+                    LobbyButton::Leaderboard => {
+                        close_lobby(&mut commands, &modals);
+                        leaderboard.send(ShowLeaderboard);
+                    },
+                    LobbyButton::Back => {
+                        close_lobby(&mut commands, &modals);
+                        draw_main_menu(&mut commands, &options);
+                    },
+                    LobbyButton::CopyLink(link) => copyToClipboard(link)
+                }
+            }
+        }
+    }
+}
+
+fn code_typing(
+    mut keys: EventReader<KeyboardInput>,
+    mut code_input: Query<(&mut CodeInput, &mut Text), (With<CodeInput>, bevy::ecs::query::Without<CodeStatus>)>,
+    mut code_status: Query<&mut Text, With<CodeStatus>>,
+    mut choice: EventWriter<LobbyChoice>
+) {
+    let Ok((mut code_input, mut text)) = code_input.get_single_mut() else {
+        keys.clear();
+        return;
+    };
+
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        match &key.logical_key {
+            Key::Character(typed) => {
+                for c in typed.chars().filter(char::is_ascii_alphanumeric) {
+                    if code_input.0.len() < ROOM_CODE_LEN {
+                        code_input.0.push(c.to_ascii_uppercase());
+                    }
+                }
+            },
+            Key::Backspace => {
+                code_input.0.pop();
+            },
+            Key::Enter => submit_code(&code_input.0, &mut code_status, &mut choice),
+            _ => {}
+        }
+        text.0 = code_placeholder(&code_input.0);
+    }
+}
+
+fn lobby_processor(
+    mut commands: Commands,
+    options: Res<LobbyOptions>,
+    modals: Query<Entity, With<LobbyModal>>,
+    mut code_status: Query<&mut Text, With<CodeStatus>>,
+    mut search_progress: Query<&mut Text, (With<SearchProgress>, Without<CodeStatus>)>,
+    mut event_queue: EventReader<MetaEvent>
+) {
+    for event in event_queue.read() {
+        match event {
+            MetaEvent::Searching { rating, window } => {
+                for mut progress in search_progress.iter_mut() {
+                    let lowest = rating.saturating_sub_unsigned(*window);
+                    let highest = rating.saturating_add_unsigned(*window);
+                    progress.0 = format!("Your rating: {rating}\nOpponents from {lowest} to {highest}");
+                }
+            },
+            MetaEvent::RoomCreated(code) => {
+                close_lobby(&mut commands, &modals);
+                draw_room_modal(&mut commands, code);
+            },
+            MetaEvent::RoomNotFound(code) => {
+                if let Ok(mut status) = code_status.get_single_mut() {
+                    status.0 = format!("No room with code {code}");
+                } else {
+                    // We came from an invite link, so there's no entry form yet.
+                    close_lobby(&mut commands, &modals);
+                    draw_code_entry(&mut commands, &format!("No room with code {code}"));
+                }
+            },
+            MetaEvent::GameNotFound(game_id) => {
+                close_lobby(&mut commands, &modals);
+                draw_modal(&mut commands, |parent| {
+                    spawn_label(parent, &format!("Game {game_id} is not running"));
+                    spawn_button(parent, "Back", LobbyButton::Back);
+                });
+            },
+            MetaEvent::Rejected(reason) => {
+                // Nothing here works without the server, so there is no way back.
+                close_lobby(&mut commands, &modals);
+                draw_message_modal(&mut commands, reason.clone());
+            },
+            MetaEvent::ConnectionLost => {
+                close_lobby(&mut commands, &modals);
+                draw_message_modal(&mut commands, String::from("Lost the connection to the server, reload the page to try again"));
+            },
+            MetaEvent::ReplayNotFound(game_id) => {
+                close_lobby(&mut commands, &modals);
+                draw_modal(&mut commands, |parent| {
+                    spawn_label(parent, &format!("No replay of game {game_id}"));
+                    spawn_button(parent, "Back", LobbyButton::Back);
+                });
+            },
+            MetaEvent::OpponentFound | MetaEvent::Spectating { .. } | MetaEvent::ReplayLoaded { .. } => close_lobby(&mut commands, &modals),
+            MetaEvent::LeaderboardClosed => draw_main_menu(&mut commands, &options),
+            _ => {}
+        }
+    }
+}
+
+fn submit_code(
+    code: &str,
+    code_status: &mut Query<&mut Text, With<CodeStatus>>,
+    choice: &mut EventWriter<LobbyChoice>
+) {
+    let status = if code.len() == ROOM_CODE_LEN {
+        choice.send(LobbyChoice::JoinRoom(code.to_string()));
+        format!("Joining room {code}...")
+    } else {
+        format!("Codes are {ROOM_CODE_LEN} characters long")
+    };
+    if let Ok(mut text) = code_status.get_single_mut() {
+        text.0 = status;
+    }
+}
+
+fn close_lobby(
+    commands: &mut Commands,
+    modals: &Query<Entity, With<LobbyModal>>
+) {
+    for modal in modals.iter() {
+        commands.entity(modal).try_despawn_recursive();
+    }
+}
+
+fn code_placeholder(code: &str) -> String {
+    format!("{code:_<ROOM_CODE_LEN$}")
+}
+
+fn draw_main_menu(commands: &mut Commands, options: &LobbyOptions) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, "Tic-tac-toe");
+        spawn_button(parent, &format!("Board: {}", offered_variant(options.variant).0), LobbyButton::NextVariant);
+        if options.online {
+            // Only the server keeps time, so local games have no clock.
+            spawn_button(parent, &format!("Clock: {}", offered_clock(options.clock).0), LobbyButton::NextClock);
+            spawn_button(parent, "Quick match", LobbyButton::QuickMatch);
+            spawn_button(parent, "Create room", LobbyButton::CreateRoom);
+            spawn_button(parent, "Join room", LobbyButton::EnterCode);
+            spawn_button(parent, "Leaderboard", LobbyButton::Leaderboard);
+        }
+        spawn_button(parent, "Play the computer", LobbyButton::ChooseBot);
+        spawn_button(parent, "Two players, one screen", LobbyButton::HotSeat);
+    });
+}
+
+fn draw_bot_menu(commands: &mut Commands) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, "How good should it be?");
+        spawn_button(parent, "Easy", LobbyButton::PlayBot(Difficulty::Random));
+        spawn_button(parent, "Medium", LobbyButton::PlayBot(Difficulty::Heuristic));
+        spawn_button(parent, "Unbeatable", LobbyButton::PlayBot(Difficulty::Perfect));
+        spawn_button(parent, "Back", LobbyButton::Back);
+    });
+}
+
+fn draw_side_menu(commands: &mut Commands, difficulty: Difficulty) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, "Who moves first?");
+        spawn_button(parent, "Me, as X", LobbyButton::LocalComputer(difficulty, Player::X));
+        spawn_button(parent, "The computer", LobbyButton::LocalComputer(difficulty, Player::O));
+        spawn_button(parent, "Back", LobbyButton::Back);
+    });
+}
+
+fn draw_message_modal(commands: &mut Commands, txt: String) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, &txt);
+    });
+}
+
+fn draw_search_modal(commands: &mut Commands) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, "Searching opponent...");
+        parent.spawn((
+            SearchProgress,
+            Text::new(""),
+            TextColor(TEXT_COLOR),
+            TextLayout {justify: JustifyText::Center, ..default()}
+        ));
+    });
+}
+
+fn draw_room_modal(commands: &mut Commands, code: &str) {
+    let link = inviteLink(code);
+    draw_modal(commands, |parent| {
+        spawn_label(parent, &format!("Room code: {code}"));
+        spawn_label(parent, &link);
+        spawn_button(parent, "Copy link", LobbyButton::CopyLink(link.clone()));
+        spawn_label(parent, "Waiting for your friend...");
+    });
+}
+
+fn draw_code_entry(commands: &mut Commands, status: &str) {
+    draw_modal(commands, |parent| {
+        spawn_label(parent, "Enter the room code");
+        parent.spawn((
+            CodeInput(String::new()),
+            Text::new(code_placeholder("")),
+            TextColor(Color::WHITE),
+            TextLayout {justify: JustifyText::Center, ..default()}
+        ));
+        parent.spawn((
+            CodeStatus,
+            Text::new(status),
+            TextColor(TEXT_COLOR),
+            TextLayout {justify: JustifyText::Center, ..default()}
+        ));
+        spawn_button(parent, "Join", LobbyButton::Join);
+        spawn_button(parent, "Back", LobbyButton::Back);
+    });
+}
+
+fn draw_modal(
+    commands: &mut Commands,
+    content: impl FnOnce(&mut ChildBuilder)
+) {
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(LobbyModal)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(360.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(12.0),
+                padding: UiRect::all(Val::Px(20.0)),
+                ..Default::default()
+                },
+                BackgroundColor(PANEL_COLOR)
+        ))
+        .with_children(content);
+    });
+}
+
+fn spawn_label(parent: &mut ChildBuilder, txt: &str) {
+    parent.spawn((
+        Text::new(txt),
+        TextColor(TEXT_COLOR),
+        TextLayout {justify: JustifyText::Center, ..default()}
+    ));
+}
+
+fn spawn_button(parent: &mut ChildBuilder, label: &str, action: LobbyButton) {
+    parent
+        .spawn((
+            Button,
+            action,
+            Node {
+                width: Val::Px(220.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(BUTTON_COLOR)
+        ))
+        .with_children(|button| {
+            button.spawn((Text::new(label), TextColor(Color::WHITE)));
+        });
+}
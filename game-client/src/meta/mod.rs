@@ -1 +1,2 @@
+pub mod lobby;
 pub mod ui;
\ No newline at end of file
@@ -1,81 +1,321 @@
-use bevy::{app::{Plugin, Startup, Update}, color::Color, ecs::{component::Component, entity::Entity, event::{Event, EventReader}, query::With, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Commands, Query, Single}}, hierarchy::{BuildChildren, ChildBuild, DespawnRecursiveExt}, text::{JustifyText, TextColor, TextLayout}, ui::{widget::Text, AlignItems, BackgroundColor, JustifyContent, Node, PositionType, UiRect, Val}, utils::default};
+use bevy::{app::{Plugin, Update}, color::Color, ecs::{component::Component, entity::Entity, event::{Event, EventReader, EventWriter}, query::{Changed, With, Without}, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Commands, Local, Query, Res, ResMut, Resource, Single}}, hierarchy::{BuildChildren, ChildBuild, DespawnRecursiveExt}, time::{Time, Timer, TimerMode}, text::{JustifyText, TextColor, TextLayout}, ui::{widget::{Button, Text}, AlignItems, BackgroundColor, FlexDirection, Interaction, JustifyContent, Node, PositionType, RelativeCursorPosition, UiRect, Val}, utils::default};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use game_core::{Player, Variant};
+use js_sys::Function;
+use serde::Deserialize;
+use wasm_bindgen::{prelude::Closure, JsCast};
 
 use crate::console_log;
+use crate::javascript::bindings::fetchLeaderboard;
 use crate::log;
+use crate::meta::lobby::{offered_variant, LobbyChoice, BUTTON_COLOR, BUTTON_HOVER_COLOR, VARIANT_COUNT};
+
+// Name and length in days of the periods the leaderboard can cover.
+const LEADERBOARD_PERIODS: [(&str, Option<u32>); 4] = [
+    ("All time", None),
+    ("Last 30 days", Some(30)),
+    ("Last 7 days", Some(7)),
+    ("Today", Some(1))
+];
 
 pub struct GameUI;
 
 impl Plugin for GameUI {
     fn build(&self, app: &mut bevy::app::App) {
+        let (sender, receiver) = unbounded();
         app
             .add_event::<MetaEvent>()
-            .add_systems(Startup, (draw_searching_modal))
-            .add_systems(Update, (searching_processor, finish_processor).run_if(on_event::<MetaEvent>))
+            .add_event::<ShowLeaderboard>()
+            .add_event::<ReplayControl>()
+            .insert_resource(LeaderboardFeed { sender, receiver })
+            .init_resource::<LeaderboardFilter>()
+            .add_systems(Update, (finish_processor, opponent_left_processor, spectating_processor, series_processor, replay_processor, toast_processor).run_if(on_event::<MetaEvent>))
+            .add_systems(Update, show_leaderboard.run_if(on_event::<ShowLeaderboard>))
+            .add_systems(Update, (rematch_button, leaderboard_buttons, leaderboard_loaded, replay_buttons, replay_scrub, expire_toasts))
         ;
     }
 }
 
-fn searching_processor(
+fn finish_processor(
     mut commands: Commands,
-    searching_modal: Query<Entity, With<SearchingOpponentModal>>,
+    modals: Query<Entity, With<FinalModal>>,
+    mut rematch_status: Query<&mut Text, (With<RematchStatus>, Without<RatingStatus>)>,
+    mut rating_status: Query<&mut Text, With<RatingStatus>>,
+    // The opponent may ask, and the rating may come, before our modal is up.
+    mut opponent_asked: Local<bool>,
+    mut rating: Local<Option<(i32, i32)>>,
     mut event_queue: EventReader<MetaEvent> 
 ) {
-    let searching_modal = searching_modal.iter().nth(0);
+    for event in event_queue.read() {
+        match event {
+            MetaEvent::GameFinished { result, rematch } => {
+                let txt = match result {
+                    GameResult::Won => "You won!!!",
+                    GameResult::Lost => "You lost!!!",
+                    GameResult::Draw | GameResult::Winner(None) => "Draw!!!",
+                    GameResult::Winner(Some(Player::X)) => "X won!!!",
+                    GameResult::Winner(Some(Player::O)) => "O won!!!"
+                };
+                console_log!("finish processor got event result: {:?}", result);
+                let status = if *opponent_asked { "Opponent wants a rematch" } else { "" };
+                let rating_txt = rating.map(|(rating, delta)| rating_text(rating, delta)).unwrap_or_default();
+                draw_final_modal(&mut commands, String::from(txt), rating_txt, rematch.then_some(status));
+            },
+            MetaEvent::RatingChanged { rating: new_rating, delta } => {
+                *rating = Some((*new_rating, *delta));
+                for mut status in rating_status.iter_mut() {
+                    status.0 = rating_text(*new_rating, *delta);
+                }
+            },
+            MetaEvent::RematchRequested => {
+                *opponent_asked = true;
+                for mut status in rematch_status.iter_mut() {
+                    status.0 = String::from("Opponent wants a rematch");
+                }
+            },
+            MetaEvent::OpponentFound | MetaEvent::Spectating { .. } | MetaEvent::RematchAccepted { .. } => {
+                *opponent_asked = false;
+                *rating = None;
+                for modal in modals.iter() {
+                    commands.entity(modal).try_despawn_recursive();
+                }
+            },
+            _ => {}
+        }
+    }
+}
 
-    if let Some(searching_modal) = searching_modal {
-        for event in event_queue.read() {
-            match event {
-                MetaEvent::OpponentFound => {
-                    commands.entity(searching_modal).try_despawn_recursive();    
-                },
-                _ => {}
+fn rating_text(rating: i32, delta: i32) -> String {
+    format!("Rating: {rating} ({delta:+})")
+}
+
+fn rematch_button(
+    mut buttons: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<RematchButton>)>,
+    mut rematch_status: Query<&mut Text, With<RematchStatus>>,
+    mut choice: EventWriter<LobbyChoice>
+) {
+    for (interaction, mut background) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => *background = BackgroundColor(BUTTON_HOVER_COLOR),
+            Interaction::None => *background = BackgroundColor(BUTTON_COLOR),
+            Interaction::Pressed => {
+                for mut status in rematch_status.iter_mut() {
+                    status.0 = String::from("Waiting for your opponent...");
+                }
+                choice.send(LobbyChoice::Rematch);
             }
         }
     }
 }
 
-fn finish_processor(
-    commands: Commands,
-    mut event_queue: EventReader<MetaEvent> 
+fn series_processor(
+    mut commands: Commands,
+    bars: Query<Entity, With<SeriesBar>>,
+    mut event_queue: EventReader<MetaEvent>
+) {
+    for event in event_queue.read() {
+        if let MetaEvent::RematchAccepted { wins, losses, draws } = event {
+            for bar in bars.iter() {
+                commands.entity(bar).try_despawn_recursive();
+            }
+            draw_series_bar(&mut commands, *wins, *losses, *draws);
+        }
+    }
+}
+
+fn opponent_left_processor(
+    mut commands: Commands,
+    banners: Query<Entity, With<OpponentLeftBanner>>,
+    mut event_queue: EventReader<MetaEvent>
 ) {
     for event in event_queue.read() {
         match event {
-            MetaEvent::GameFinished(is_win) => {
-                let txt = match is_win {
-                    true => "won",
-                    false => "lost"
-                };
-                console_log!("finish processor got event is_win: {is_win}");
-                draw_final_modal(commands, String::from(txt));
-                break;
+            MetaEvent::OpponentLeft(forfeit_in_secs) => {
+                for banner in banners.iter() {
+                    commands.entity(banner).try_despawn_recursive();
+                }
+                draw_opponent_left_banner(&mut commands, *forfeit_in_secs);
+            },
+            MetaEvent::OpponentReturned | MetaEvent::GameFinished { .. } => {
+                for banner in banners.iter() {
+                    commands.entity(banner).try_despawn_recursive();
+                }
             },
             _ => {}
         }
     }
 }
 
+// How long a toast stays up.
+const TOAST_SECS: f32 = 3.;
+
+/// A short note at the top of the screen that goes away by itself.
 #[derive(Component)]
-struct SearchingOpponentModal;
+struct Toast {
+    timer: Timer
+}
+
+fn toast_processor(
+    mut commands: Commands,
+    toasts: Query<Entity, With<Toast>>,
+    mut event_queue: EventReader<MetaEvent>
+) {
+    for event in event_queue.read() {
+        if let MetaEvent::Refused(reason) = event {
+            // Only the latest one matters.
+            for toast in toasts.iter() {
+                commands.entity(toast).try_despawn_recursive();
+            }
+            draw_toast(&mut commands, reason);
+        }
+    }
+}
+
+fn expire_toasts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toasts: Query<(Entity, &mut Toast)>
+) {
+    for (entity, mut toast) in toasts.iter_mut() {
+        if toast.timer.tick(time.delta()).just_finished() {
+            commands.entity(entity).try_despawn_recursive();
+        }
+    }
+}
 
-fn draw_searching_modal(
-    mut commands: Commands
+fn draw_toast(
+    commands: &mut Commands,
+    txt: &str
 ) {
     commands
         .spawn(Node {
             width: Val::Percent(100.0),
             height: Val::Percent(100.0),
-            align_items: AlignItems::Center,
+            align_items: AlignItems::FlexStart,
             justify_content: JustifyContent::Center,
             ..default()
         })
-        .insert(SearchingOpponentModal)
+        .insert(Toast { timer: Timer::from_seconds(TOAST_SECS, TimerMode::Once) })
     .with_children(|parent| {
             parent.spawn((Node {
-                width: Val::Px(320.0),
-                height: Val::Px(150.0),
-                position_type: PositionType::Absolute,
+                width: Val::Px(420.0),
+                height: Val::Px(40.0),
                 margin: UiRect {
-                    top: Val::Px(50.0),
+                    // Below the opponent-left banner, which may be up too.
+                    top: Val::Px(70.0),
+                    ..Default::default()
+                },
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+                },
+                BackgroundColor(Color::srgb(0.25, 0.25, 0.25))
+        ))
+        .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
+            parent.spawn(
+               (Text::new(txt),
+                TextColor(Color::srgb(0.941, 0.941, 0.941)),
+                TextLayout {justify: JustifyText::Center, ..default()}
+            ));
+        });
+    });
+}
+
+#[derive(Component)]
+struct FinalModal;
+
+#[derive(Component)]
+struct RematchButton;
+
+#[derive(Component)]
+struct RematchStatus;
+
+#[derive(Component)]
+struct RatingStatus;
+
+#[derive(Component)]
+struct SeriesBar;
+
+fn spectating_processor(
+    mut commands: Commands,
+    bars: Query<Entity, With<PlayersBar>>,
+    mut event_queue: EventReader<MetaEvent>
+) {
+    for event in event_queue.read() {
+        if let MetaEvent::Spectating { x_label, o_label } = event {
+            // A rematch swaps the labels, so the old bar goes.
+            for bar in bars.iter() {
+                commands.entity(bar).try_despawn_recursive();
+            }
+            draw_players_bar(&mut commands, x_label, o_label);
+        }
+    }
+}
+
+#[derive(Component)]
+struct OpponentLeftBanner;
+
+#[derive(Component)]
+struct PlayersBar;
+
+fn draw_opponent_left_banner(
+    commands: &mut Commands,
+    forfeit_in_secs: u32
+) {
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::FlexStart,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(OpponentLeftBanner)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(420.0),
+                height: Val::Px(50.0),
+                margin: UiRect {
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+                },
+                BackgroundColor(Color::srgb(0.820, 0.376, 0.376))
+        ))
+        .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
+            parent.spawn(
+               (Text::new(format!("Opponent left, you win in {forfeit_in_secs}s unless they return")),
+                TextColor(Color::srgb(0.941, 0.941, 0.941)),
+                TextLayout {justify: JustifyText::Center, ..default()}
+            ));
+        });
+    });
+}
+
+fn draw_players_bar(
+    commands: &mut Commands,
+    x_label: &str,
+    o_label: &str
+) {
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::FlexEnd,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(PlayersBar)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(420.0),
+                height: Val::Px(50.0),
+                margin: UiRect {
+                    bottom: Val::Px(10.0),
                     ..Default::default()
                 },
                 align_items: AlignItems::Center,
@@ -86,17 +326,60 @@ fn draw_searching_modal(
         ))
         .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
             parent.spawn(
-               (Text::new("Searching opponent..."),
+               (Text::new(format!("X: {x_label}  vs  O: {o_label}  (watching)")),
                 TextColor(Color::srgb(0.941, 0.941, 0.286)),
-                TextLayout {justify: JustifyText::Right, ..default()}
+                TextLayout {justify: JustifyText::Center, ..default()}
             ));
         });
     });
 }
 
+fn draw_series_bar(
+    commands: &mut Commands,
+    wins: u32,
+    losses: u32,
+    draws: u32
+) {
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::FlexEnd,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(SeriesBar)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(420.0),
+                height: Val::Px(50.0),
+                margin: UiRect {
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+                },
+                BackgroundColor(Color::srgb(0.376, 0.376, 0.820))
+        ))
+        .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
+            parent.spawn(
+               (Text::new(format!("Series: {wins} won, {losses} lost, {draws} drawn")),
+                TextColor(Color::srgb(0.941, 0.941, 0.286)),
+                TextLayout {justify: JustifyText::Center, ..default()}
+            ));
+        });
+    });
+}
+
+/// The result and, after rated games, the new rating, with a rematch
+/// button and its status line under it when a rematch is on offer.
 fn draw_final_modal(
-    mut commands: Commands,
-    txt: String
+    commands: &mut Commands,
+    txt: String,
+    rating: String,
+    rematch_status: Option<&str>
 ) {
     commands
         .spawn(Node {
@@ -106,16 +389,18 @@ fn draw_final_modal(
             justify_content: JustifyContent::Center,
             ..default()
         })
-        .insert(SearchingOpponentModal)
+        .insert(FinalModal)
     .with_children(|parent| {
             parent.spawn((Node {
                 width: Val::Px(320.0),
-                height: Val::Px(150.0),
+                height: Val::Px(180.0),
                 position_type: PositionType::Absolute,
                 margin: UiRect {
                     top: Val::Px(50.0),
                     ..Default::default()
                 },
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
                 align_items: AlignItems::Center,
                 justify_content: JustifyContent::Center,
                 ..Default::default()
@@ -124,10 +409,459 @@ fn draw_final_modal(
         ))
         .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
             parent.spawn(
-               (Text::new(format!("You {txt}!!!")),
+               (Text::new(txt),
                 TextColor(Color::srgb(0.157, 0.094, 0.647)),
                 TextLayout {justify: JustifyText::Right, ..default()}
             ));
+            parent.spawn((
+                Text::new(rating),
+                RatingStatus,
+                TextColor(Color::srgb(0.157, 0.094, 0.647))
+            ));
+            let Some(status) = rematch_status else {
+                return;
+            };
+            parent
+                .spawn((
+                    Button,
+                    RematchButton,
+                    Node {
+                        width: Val::Px(160.0),
+                        padding: UiRect::all(Val::Px(6.0)),
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    BackgroundColor(BUTTON_COLOR)
+                ))
+                .with_children(|button| {
+                    button.spawn((Text::new("Rematch"), TextColor(Color::WHITE)));
+                });
+            parent.spawn((
+                Text::new(status),
+                RematchStatus,
+                TextColor(Color::srgb(0.157, 0.094, 0.647))
+            ));
+        });
+    });
+}
+
+/// Opens the leaderboard screen, from the main menu.
+#[derive(Event)]
+pub struct ShowLeaderboard;
+
+// Answers to `fetchLeaderboard`: the filter asked for and the response
+// body, `None` if the request failed.
+#[derive(Resource)]
+struct LeaderboardFeed {
+    sender: Sender<(LeaderboardFilter, Option<String>)>,
+    receiver: Receiver<(LeaderboardFilter, Option<String>)>
+}
+
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+struct LeaderboardFilter {
+    // 0 for all boards, otherwise one past the index in `offered_variant`.
+    variant: usize,
+    // Index in `LEADERBOARD_PERIODS`.
+    period: usize
+}
+
+impl LeaderboardFilter {
+    fn variant(&self) -> Option<(&'static str, Variant)> {
+        self.variant.checked_sub(1).map(offered_variant)
+    }
+
+    fn period(&self) -> (&'static str, Option<u32>) {
+        LEADERBOARD_PERIODS[self.period]
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardPage {
+    players: Vec<Standing>,
+    you: Option<Standing>
+}
+
+#[derive(Deserialize)]
+struct Standing {
+    rank: u32,
+    label: String,
+    rating: i32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+    form: String
+}
+
+#[derive(Component)]
+struct LeaderboardScreen;
+
+#[derive(Component, Clone, Copy)]
+enum LeaderboardButton {
+    NextVariant,
+    NextPeriod,
+    Back
+}
+
+fn show_leaderboard(
+    mut commands: Commands,
+    feed: Res<LeaderboardFeed>,
+    filter: Res<LeaderboardFilter>,
+    mut events: EventReader<ShowLeaderboard>
+) {
+    for _ in events.read() {
+        draw_leaderboard(&mut commands, &filter, Err("Loading..."));
+        request_leaderboard(&feed, *filter);
+    }
+}
+
+fn leaderboard_buttons(
+    mut commands: Commands,
+    screens: Query<Entity, With<LeaderboardScreen>>,
+    mut buttons: Query<(&Interaction, &LeaderboardButton, &mut BackgroundColor), Changed<Interaction>>,
+    feed: Res<LeaderboardFeed>,
+    mut filter: ResMut<LeaderboardFilter>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    for (interaction, button, mut background) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => *background = BackgroundColor(BUTTON_HOVER_COLOR),
+            Interaction::None => *background = BackgroundColor(BUTTON_COLOR),
+            Interaction::Pressed => {
+                for screen in screens.iter() {
+                    commands.entity(screen).try_despawn_recursive();
+                }
+                match button {
+                    LeaderboardButton::NextVariant => filter.variant = (filter.variant + 1) % (VARIANT_COUNT + 1),
+                    LeaderboardButton::NextPeriod => filter.period = (filter.period + 1) % LEADERBOARD_PERIODS.len(),
+                    LeaderboardButton::Back => {
+                        meta_event.send(MetaEvent::LeaderboardClosed);
+                        continue;
+                    }
+                }
+                draw_leaderboard(&mut commands, &filter, Err("Loading..."));
+                request_leaderboard(&feed, *filter);
+            }
+        }
+    }
+}
+
+fn leaderboard_loaded(
+    mut commands: Commands,
+    screens: Query<Entity, With<LeaderboardScreen>>,
+    feed: Res<LeaderboardFeed>,
+    filter: Res<LeaderboardFilter>
+) {
+    while let Ok((requested, body)) = feed.receiver.try_recv() {
+        // Closed in the meantime, or the filter changed since we asked.
+        if screens.is_empty() || requested != *filter {
+            continue;
+        }
+        for screen in screens.iter() {
+            commands.entity(screen).try_despawn_recursive();
+        }
+        match body.and_then(|body| serde_json::from_str::<LeaderboardPage>(&body).ok()) {
+            Some(page) => draw_leaderboard(&mut commands, &filter, Ok(&page)),
+            None => draw_leaderboard(&mut commands, &filter, Err("Could not load the leaderboard"))
+        }
+    }
+}
+
+fn request_leaderboard(feed: &LeaderboardFeed, filter: LeaderboardFilter) {
+    let sender = feed.sender.clone();
+    let handler = Closure::once_into_js(move |body: Option<String>| {
+        sender.send((filter, body)).ok();
+    });
+    let variant = filter.variant().map(|(_, variant)| messages::variant_slug(variant));
+    fetchLeaderboard(variant, filter.period().1, handler.unchecked_ref::<Function>());
+}
+
+/// The filters, then the top players and our own standing, or `Err`
+/// with a line to show in their place.
+fn draw_leaderboard(
+    commands: &mut Commands,
+    filter: &LeaderboardFilter,
+    page: Result<&LeaderboardPage, &str>
+) {
+    let text_color = Color::srgb(0.941, 0.941, 0.286);
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(LeaderboardScreen)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(460.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(8.0),
+                padding: UiRect::all(Val::Px(20.0)),
+                ..Default::default()
+                },
+                BackgroundColor(Color::srgb(0.376, 0.376, 0.820))
+        ))
+        .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
+            parent.spawn((Text::new("Leaderboard"), TextColor(text_color)));
+            let board = filter.variant().map_or("All boards", |(name, _)| name);
+            spawn_leaderboard_button(parent, &format!("Board: {board}"), LeaderboardButton::NextVariant);
+            spawn_leaderboard_button(parent, &format!("Period: {}", filter.period().0), LeaderboardButton::NextPeriod);
+            match page {
+                Ok(page) => {
+                    if page.players.is_empty() {
+                        parent.spawn((Text::new("No rated games yet"), TextColor(text_color)));
+                    }
+                    for standing in &page.players {
+                        parent.spawn((Text::new(standing_text(standing)), TextColor(text_color)));
+                    }
+                    let you = match &page.you {
+                        Some(you) => format!("You: #{}, rating {}, form {}", you.rank, you.rating, you.form),
+                        None => String::from("You: no rated games here yet")
+                    };
+                    parent.spawn((Text::new(you), TextColor(Color::WHITE)));
+                },
+                Err(status) => {
+                    parent.spawn((Text::new(status), TextColor(text_color)));
+                }
+            }
+            spawn_leaderboard_button(parent, "Back", LeaderboardButton::Back);
+        });
+    });
+}
+
+fn standing_text(standing: &Standing) -> String {
+    format!(
+        "{}. {}  {}  {}-{}-{}  {}",
+        standing.rank, standing.label, standing.rating, standing.wins, standing.draws, standing.losses, standing.form
+    )
+}
+
+fn spawn_leaderboard_button(parent: &mut bevy::hierarchy::ChildBuilder, label: &str, action: LeaderboardButton) {
+    parent
+        .spawn((
+            Button,
+            action,
+            Node {
+                width: Val::Px(220.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(BUTTON_COLOR)
+        ))
+        .with_children(|button| {
+            button.spawn((Text::new(label), TextColor(Color::WHITE)));
+        });
+}
+
+/// Steps through a replay, from the replay bar or the keyboard.
+#[derive(Event, Clone, Copy, Debug)]
+pub enum ReplayControl {
+    First,
+    Back,
+    TogglePlay,
+    Forward,
+    Last,
+    // Show the board after this many moves.
+    Seek(usize)
+}
+
+#[derive(Component)]
+struct ReplayBar;
+
+#[derive(Component)]
+struct ReplayStatus;
+
+#[derive(Component)]
+struct PlayLabel;
+
+// The bar that scrubs through the moves, and how many there are.
+#[derive(Component)]
+struct ReplayTrack {
+    total: usize
+}
+
+// The part of the track already played.
+#[derive(Component)]
+struct ReplayProgress;
+
+#[derive(Component, Clone, Copy)]
+struct ReplayButton(ReplayControl);
+
+fn replay_processor(
+    mut commands: Commands,
+    bars: Query<Entity, With<ReplayBar>>,
+    mut status: Query<&mut Text, (With<ReplayStatus>, Without<PlayLabel>)>,
+    mut play_label: Query<&mut Text, With<PlayLabel>>,
+    mut track: Query<&mut ReplayTrack>,
+    mut progress: Query<&mut Node, With<ReplayProgress>>,
+    mut event_queue: EventReader<MetaEvent>
+) {
+    for event in event_queue.read() {
+        match event {
+            MetaEvent::ReplayLoaded { x_label, o_label } => {
+                for bar in bars.iter() {
+                    commands.entity(bar).try_despawn_recursive();
+                }
+                draw_replay_bar(&mut commands, x_label, o_label);
+            },
+            MetaEvent::ReplayPosition { position, total, playing, status: txt } => {
+                for mut text in status.iter_mut() {
+                    text.0 = txt.clone();
+                }
+                for mut text in play_label.iter_mut() {
+                    text.0 = String::from(if *playing { "Pause" } else { "Play" });
+                }
+                for mut track in track.iter_mut() {
+                    track.total = *total;
+                }
+                let played = if *total == 0 { 0. } else { *position as f32 / *total as f32 * 100. };
+                for mut node in progress.iter_mut() {
+                    node.width = Val::Percent(played);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn replay_buttons(
+    mut buttons: Query<(&Interaction, &ReplayButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut controls: EventWriter<ReplayControl>
+) {
+    for (interaction, button, mut background) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => *background = BackgroundColor(BUTTON_HOVER_COLOR),
+            Interaction::None => *background = BackgroundColor(BUTTON_COLOR),
+            Interaction::Pressed => {
+                controls.send(button.0);
+            }
+        }
+    }
+}
+
+/// Seeks to wherever the track is pressed, following the cursor while
+/// the button is held.
+fn replay_scrub(
+    tracks: Query<(&Interaction, &RelativeCursorPosition, &ReplayTrack)>,
+    mut last_seek: Local<Option<usize>>,
+    mut controls: EventWriter<ReplayControl>
+) {
+    for (interaction, cursor, track) in tracks.iter() {
+        let target = match (interaction, cursor.normalized) {
+            (Interaction::Pressed, Some(cursor)) => (cursor.x.clamp(0., 1.) * track.total as f32).round() as usize,
+            _ => {
+                *last_seek = None;
+                continue;
+            }
+        };
+        if *last_seek != Some(target) {
+            *last_seek = Some(target);
+            controls.send(ReplayControl::Seek(target));
+        }
+    }
+}
+
+fn draw_replay_bar(
+    commands: &mut Commands,
+    x_label: &str,
+    o_label: &str
+) {
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            align_items: AlignItems::FlexEnd,
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .insert(ReplayBar)
+    .with_children(|parent| {
+            parent.spawn((Node {
+                width: Val::Px(420.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                margin: UiRect {
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                align_items: AlignItems::Center,
+                ..Default::default()
+                },
+                BackgroundColor(Color::srgb(0.376, 0.376, 0.820))
+        ))
+        .with_children(|parent: &mut bevy::hierarchy::ChildBuilder<'_>| {
+            parent.spawn(
+               (Text::new(format!("X: {x_label}  vs  O: {o_label}  (replay)")),
+                TextColor(Color::srgb(0.941, 0.941, 0.286)),
+                TextLayout {justify: JustifyText::Center, ..default()}
+            ));
+            parent.spawn((
+                ReplayStatus,
+                Text::new(""),
+                TextColor(Color::WHITE),
+                TextLayout {justify: JustifyText::Center, ..default()}
+            ));
+            parent
+                .spawn((
+                    Button,
+                    ReplayTrack { total: 0 },
+                    RelativeCursorPosition::default(),
+                    Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(12.0),
+                        ..default()
+                    },
+                    BackgroundColor(BUTTON_COLOR)
+                ))
+                .with_children(|track| {
+                    track.spawn((
+                        ReplayProgress,
+                        Node {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.941, 0.941, 0.286))
+                    ));
+                });
+            parent
+                .spawn(Node {
+                    column_gap: Val::Px(6.0),
+                    ..default()
+                })
+                .with_children(|buttons| {
+                    let controls = [
+                        ("|<", ReplayControl::First),
+                        ("<", ReplayControl::Back),
+                        ("Pause", ReplayControl::TogglePlay),
+                        (">", ReplayControl::Forward),
+                        (">|", ReplayControl::Last)
+                    ];
+                    for (label, control) in controls {
+                        buttons
+                            .spawn((
+                                Button,
+                                ReplayButton(control),
+                                Node {
+                                    min_width: Val::Px(48.0),
+                                    padding: UiRect::all(Val::Px(6.0)),
+                                    justify_content: JustifyContent::Center,
+                                    ..default()
+                                },
+                                BackgroundColor(BUTTON_COLOR)
+                            ))
+                            .with_children(|button| {
+                                let mut text = button.spawn((Text::new(label), TextColor(Color::WHITE)));
+                                if matches!(control, ReplayControl::TogglePlay) {
+                                    text.insert(PlayLabel);
+                                }
+                            });
+                    }
+                });
         });
     });
 }
@@ -135,5 +869,42 @@ fn draw_final_modal(
 #[derive(Event)]
 pub enum MetaEvent {
     OpponentFound,
-    GameFinished(bool) // whether win or not
+    OpponentLeft(u32), // seconds until they forfeit
+    OpponentReturned,
+    RoomCreated(String),
+    RoomNotFound(String),
+    GameNotFound(u64),
+    Spectating { x_label: String, o_label: String },
+    // `rematch` when the same opponent can be asked for another game.
+    GameFinished { result: GameResult, rematch: bool },
+    RematchRequested,
+    // The series so far, just before the next game starts.
+    RematchAccepted { wins: u32, losses: u32, draws: u32 },
+    // Still in the queue, accepting opponents `window` points either way.
+    Searching { rating: i32, window: u32 },
+    // After a rated game, may come before or after GameFinished.
+    RatingChanged { rating: i32, delta: i32 },
+    // Back to the main menu from the leaderboard.
+    LeaderboardClosed,
+    // A replay is on screen, at its first move.
+    ReplayLoaded { x_label: String, o_label: String },
+    ReplayNotFound(u64),
+    // Where the replay is after every step.
+    ReplayPosition { position: usize, total: usize, playing: bool, status: String },
+    // The server won't talk to this build, and says why.
+    Rejected(String),
+    // The page gave up reconnecting to the server.
+    ConnectionLost,
+    // The server refused something we sent, and why.
+    Refused(String)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    Won,
+    Lost,
+    Draw,
+    // For screens that root for neither side, like spectators and
+    // hot-seat games: the winner, if anyone won.
+    Winner(Option<Player>)
 }
\ No newline at end of file
@@ -1,76 +1,143 @@
-use bevy::{app::{App, Plugin, Update}, ecs::{event::{Event, EventReader, EventWriter}, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Res, Resource}}};
-use crossbeam::channel::{bounded, Receiver};
+use bevy::{app::{App, Plugin, Update}, ecs::{event::{Event, EventReader, EventWriter}, schedule::{common_conditions::on_event, IntoSystemConfigs}, system::{Res, ResMut, Resource}}};
+use crossbeam::channel::{unbounded, Receiver};
 use js_sys::Function;
-use messages::game::{server_message, PlayerMove, ServerMessage};
+use messages::capability;
+use messages::game::{ClientMessage, Hello, ResyncRequest, ServerMessage, client_message, server_message};
 use prost::Message;
 use wasm_bindgen::{prelude::Closure, JsCast};
 use crate::javascript::bindings::log;
 
-use crate::{console_log, javascript::bindings::{listenToSocketData, sendDataToSocket}};
+use crate::{console_log, javascript::bindings::{listenToSocketData, listenToSocketLost, sendDataToSocket, setSocketHello}};
 
 #[derive(Resource)]
 struct UpdateReceiver {
-    reciever: Receiver<InnerState>
+    reciever: Receiver<InnerState>,
+    // Fires once the page has given up reconnecting.
+    lost: Receiver<()>
 }
 
 struct InnerState {
     data: Vec<u8>,
 }
 
+/// How far along the current game's numbered messages are.
+#[derive(Resource, Default)]
+struct GameStream {
+    // The seq of the last message passed on.
+    last: u32,
+    // Set after a gap, until the snapshot asked for arrives.
+    resyncing: bool
+}
+
+enum Delivery {
+    Pass,
+    Drop,
+    // A message went missing, so the game has to be sent again.
+    Resync
+}
+
+impl GameStream {
+    fn check(&mut self, message: &ServerMessage) -> Delivery {
+        // Outside any game.
+        if message.seq == 0 {
+            return Delivery::Pass;
+        }
+        // Both start the count over and carry everything there is to know.
+        if matches!(message.message, Some(server_message::Message::InitGame(_) | server_message::Message::GameSnapshot(_))) {
+            self.last = message.seq;
+            self.resyncing = false;
+            return Delivery::Pass;
+        }
+        if self.resyncing || message.seq <= self.last {
+            return Delivery::Drop;
+        }
+        if message.seq > self.last + 1 {
+            self.resyncing = true;
+            return Delivery::Resync;
+        }
+        self.last = message.seq;
+        Delivery::Pass
+    }
+}
+
 pub struct SocketPlugin;
 
 impl Plugin for SocketPlugin {
     fn build(&self, app: &mut App) {
-        let (tx, rx) = bounded::<InnerState>(1);
+        // Unbounded, as the socket may deliver several messages between frames.
+        let (tx, rx) = unbounded::<InnerState>();
+
+        let (lost_tx, lost_rx) = unbounded::<()>();
 
         let state = UpdateReceiver {
-            reciever: rx
+            reciever: rx,
+            lost: lost_rx
         };
         
         let closure  = Closure::wrap(Box::new(move |x: js_sys::ArrayBuffer| {
             let uint8_array = js_sys::Uint8Array::new(&x);
             let x = uint8_array.to_vec();
-            console_log!("socked plugin closure called {:?}", x);
             tx.send(InnerState { data: x }).ok();
         }) as Box<dyn FnMut(js_sys::ArrayBuffer)>);    
         let function: Function = closure.into_js_value().dyn_into().unwrap();
     
         listenToSocketData(&function);
+
+        let lost = Closure::wrap(Box::new(move || {
+            lost_tx.send(()).ok();
+        }) as Box<dyn FnMut()>);
+        listenToSocketLost(lost.into_js_value().unchecked_ref());
+
+        // Goes out first on every connection, reconnects included.
+        let hello = Hello {
+            protocol_version: messages::PROTOCOL_VERSION,
+            capabilities: [capability::RATINGS, capability::REMATCH, capability::SPECTATING, capability::CLOCKS]
+                .iter().map(|name| name.to_string()).collect()
+        };
+        setSocketHello(ClientMessage { seq: 0, message: Some(client_message::Message::Hello(hello)) }.encode_to_vec());
     
         app
             .insert_resource(state)
+            .init_resource::<GameStream>()
             .add_systems(Update, (receive_system, send_system.run_if(on_event::<SocketSend>)))
             .add_event::<SocketRecv>()
-            .add_event::<SocketSend>();
+            .add_event::<SocketSend>()
+            .add_event::<SocketLost>();
     }
 }
 
 fn receive_system(
     state: Res<UpdateReceiver>,
-    mut ev_message: EventWriter<SocketRecv> 
+    mut stream: ResMut<GameStream>,
+    mut ev_message: EventWriter<SocketRecv>,
+    mut ev_lost: EventWriter<SocketLost>
 ) {
-    if let Ok(new_state) = state.reciever.try_recv() {
+    if state.lost.try_recv().is_ok() {
+        ev_lost.send(SocketLost);
+    }
+    while let Ok(new_state) = state.reciever.try_recv() {
         let numbers: Vec<u8> = new_state.data;
-        if let Ok(server_message) = ServerMessage::decode(&*numbers) {
-            ev_message.send(SocketRecv(server_message));
-        } else {
-            println!("socket plugin error when decoding protobuf message");
+        let Ok(server_message) = ServerMessage::decode(&*numbers) else {
+            console_log!("socket plugin error when decoding protobuf message");
+            continue;
+        };
+        match stream.check(&server_message) {
+            Delivery::Pass => {
+                ev_message.send(SocketRecv(server_message));
+            },
+            Delivery::Drop => console_log!("dropping message {} of the game, already past it", server_message.seq),
+            Delivery::Resync => {
+                console_log!("missed messages {} to {} of the game, asking for a resync", stream.last + 1, server_message.seq - 1);
+                let resync = ClientMessage { seq: 0, message: Some(client_message::Message::ResyncRequest(ResyncRequest {})) };
+                sendDataToSocket(resync.encode_to_vec());
+            }
         }
     }
 }
 
 fn send_system(mut ev_message: EventReader<SocketSend>) {
-    console_log!("send system is called");
     for SocketSend(ev) in ev_message.read() {
-        let bytes = match ev {
-            ServerMessage{message: Some(server_message::Message::PlayerMove(x))} => {
-                Some(ev.encode_to_vec())
-            },
-            _ => None // other types of messages are not supported
-        };
-        if let Some(bytes) = bytes {
-            sendDataToSocket(bytes);
-        }
+        sendDataToSocket(ev.encode_to_vec());
     }
 }
 
@@ -78,4 +145,8 @@ fn send_system(mut ev_message: EventReader<SocketSend>) {
 pub struct SocketRecv(pub ServerMessage);
 
 #[derive(Event)]
-pub struct SocketSend(pub ServerMessage);
\ No newline at end of file
+pub struct SocketSend(pub ClientMessage);
+
+/// The connection dropped and kept failing to come back.
+#[derive(Event)]
+pub struct SocketLost;
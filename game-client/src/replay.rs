@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use bevy::{app::{App, Plugin, Update}, ecs::{entity::Entity, event::{EventReader, EventWriter}, query::With, schedule::{common_conditions::{on_event, resource_exists}, IntoSystemConfigs}, system::{Commands, Query, Res, ResMut, Resource}}, hierarchy::DespawnRecursiveExt, input::{keyboard::KeyCode, ButtonInput}, time::{Time, Timer, TimerMode}};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use game_core::{Game, Move, Player, Variant};
+use js_sys::{Function, Uint8Array};
+use messages::game::{GameOutcome, Replay};
+use prost::Message;
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+
+use crate::javascript::bindings::{fetchReplay, log};
+use crate::meta::lobby::LobbyChoice;
+use crate::meta::ui::{MetaEvent, ReplayControl};
+use crate::{console_log, piece_on, Clocks, DrawRequest, GameMode, GameState, HighlightLine, Mark, PendingResult};
+
+// Moves play back at the pace they were made, kept within these bounds.
+const MIN_STEP: Duration = Duration::from_millis(300);
+const MAX_STEP: Duration = Duration::from_secs(2);
+
+/// Plays back finished games downloaded from the server, drawing them
+/// move by move the same way live games are drawn.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = unbounded();
+        app
+            .insert_resource(ReplayFeed { sender, receiver })
+            .add_systems(Update, (
+                fetch_replay.run_if(on_event::<LobbyChoice>),
+                replay_loaded,
+                replay_control.run_if(on_event::<ReplayControl>),
+                (replay_tick, replay_keys).run_if(resource_exists::<ReplayPlayer>)
+            ));
+    }
+}
+
+// Answers to `fetchReplay`: the game asked for and the downloaded
+// bytes, `None` if there is no replay to be had.
+#[derive(Resource)]
+struct ReplayFeed {
+    sender: Sender<(u64, Option<Vec<u8>>)>,
+    receiver: Receiver<(u64, Option<Vec<u8>>)>
+}
+
+/// The game being played back and how far along it is.
+#[derive(Resource)]
+struct ReplayPlayer {
+    variant: Variant,
+    // Each with when it was made, since the game started.
+    moves: Vec<(Move, Duration)>,
+    // How many moves are on the board.
+    position: usize,
+    playing: bool,
+    // Until the next move while playing.
+    timer: Timer,
+    // How the game ended, shown once the last move is on the board.
+    result: String
+}
+
+impl ReplayPlayer {
+    /// Checks every move against the rules, so a bad download can't
+    /// leave the board in a state the game couldn't reach.
+    fn new(replay: &Replay) -> Option<Self> {
+        let variant = messages::decode_variant(replay.rules.as_ref())?;
+        let mut board = Game::new(variant);
+        let mut moves = Vec::with_capacity(replay.moves.len());
+        for recorded in &replay.moves {
+            let mv = messages::decode_move(recorded.r#move.as_ref()?, board.turn())?;
+            board.apply_move(mv).ok()?;
+            moves.push((mv, Duration::from_millis(recorded.at_ms.into())));
+        }
+        let mut player = ReplayPlayer {
+            variant,
+            moves,
+            position: 0,
+            playing: true,
+            timer: Timer::new(MIN_STEP, TimerMode::Once),
+            result: result_text(replay)
+        };
+        player.wait_for_next();
+        Some(player)
+    }
+
+    fn total(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Times the gap before the move after `position`.
+    fn wait_for_next(&mut self) {
+        let Some((_, next_at)) = self.moves.get(self.position) else {
+            return;
+        };
+        let last_at = self.position.checked_sub(1).map_or(Duration::ZERO, |last| self.moves[last].1);
+        self.timer = Timer::new(next_at.saturating_sub(last_at).clamp(MIN_STEP, MAX_STEP), TimerMode::Once);
+    }
+
+    fn status(&self) -> MetaEvent {
+        let mut status = format!("Move {} of {}", self.position, self.total());
+        if self.position == self.total() {
+            status = format!("{status}, {}", self.result);
+        }
+        MetaEvent::ReplayPosition { position: self.position, total: self.total(), playing: self.playing, status }
+    }
+}
+
+fn result_text(replay: &Replay) -> String {
+    let label = |player: Player| match player {
+        Player::X => &replay.x_label,
+        Player::O => &replay.o_label
+    };
+    let winner: Player = replay.winner().into();
+    match replay.outcome() {
+        GameOutcome::XWins => format!("{} won", label(Player::X)),
+        GameOutcome::OWins => format!("{} won", label(Player::O)),
+        GameOutcome::Draw => String::from("draw"),
+        GameOutcome::Abandoned => format!("{} won, {} left", label(winner), label(winner.opposite())),
+        GameOutcome::Timeout => format!("{} won on time", label(winner))
+    }
+}
+
+fn fetch_replay(
+    feed: Res<ReplayFeed>,
+    mut ev_choice: EventReader<LobbyChoice>
+) {
+    for choice in ev_choice.read() {
+        let LobbyChoice::WatchReplay(game_id) = *choice else {
+            continue;
+        };
+        let sender = feed.sender.clone();
+        let handler = Closure::once_into_js(move |bytes: JsValue| {
+            let bytes = (!bytes.is_null()).then(|| Uint8Array::new(&bytes).to_vec());
+            sender.send((game_id, bytes)).ok();
+        });
+        fetchReplay(&game_id.to_string(), handler.unchecked_ref::<Function>());
+    }
+}
+
+fn replay_loaded(
+    mut commands: Commands,
+    marks: Query<Entity, With<Mark>>,
+    feed: Res<ReplayFeed>,
+    mut game_state: ResMut<GameState>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    while let Ok((game_id, bytes)) = feed.receiver.try_recv() {
+        let replay = bytes.and_then(|bytes| Replay::decode(&*bytes).ok());
+        let Some((replay, player)) = replay.and_then(|replay| ReplayPlayer::new(&replay).map(|player| (replay, player))) else {
+            console_log!("no usable replay of game {}", game_id);
+            meta_event.send(MetaEvent::ReplayNotFound(game_id));
+            continue;
+        };
+
+        for mark in marks.iter() {
+            commands.entity(mark).despawn_recursive();
+        }
+        commands.remove_resource::<Clocks>();
+        commands.remove_resource::<PendingResult>();
+        *game_state = GameState {
+            board: Game::new(player.variant),
+            game_finished: None,
+            is_your_turn: false,
+            me: Player::X,
+            mode: GameMode::Replay
+        };
+        meta_event.send(MetaEvent::ReplayLoaded { x_label: replay.x_label, o_label: replay.o_label });
+        meta_event.send(player.status());
+        commands.insert_resource(player);
+    }
+}
+
+fn replay_control(
+    mut commands: Commands,
+    marks: Query<Entity, With<Mark>>,
+    player: Option<ResMut<ReplayPlayer>>,
+    mut game_state: ResMut<GameState>,
+    mut controls: EventReader<ReplayControl>,
+    mut draw_queue: EventWriter<DrawRequest>,
+    mut highlight_queue: EventWriter<HighlightLine>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    let Some(mut player) = player else {
+        controls.clear();
+        return;
+    };
+    for control in controls.read() {
+        let target = match control {
+            ReplayControl::First => 0,
+            ReplayControl::Back => player.position.saturating_sub(1),
+            ReplayControl::Forward => (player.position + 1).min(player.total()),
+            ReplayControl::Last => player.total(),
+            ReplayControl::Seek(position) => (*position).min(player.total()),
+            ReplayControl::TogglePlay => {
+                player.playing = !player.playing;
+                // Playing from the end starts over.
+                if player.playing && player.position == player.total() {
+                    go_to(&mut commands, &marks, &mut game_state, &mut player, 0, &mut draw_queue, &mut highlight_queue);
+                }
+                player.wait_for_next();
+                continue;
+            }
+        };
+        // Stepping by hand stops playback.
+        player.playing = false;
+        go_to(&mut commands, &marks, &mut game_state, &mut player, target, &mut draw_queue, &mut highlight_queue);
+    }
+    meta_event.send(player.status());
+}
+
+fn replay_tick(
+    mut commands: Commands,
+    time: Res<Time>,
+    marks: Query<Entity, With<Mark>>,
+    mut player: ResMut<ReplayPlayer>,
+    mut game_state: ResMut<GameState>,
+    mut draw_queue: EventWriter<DrawRequest>,
+    mut highlight_queue: EventWriter<HighlightLine>,
+    mut meta_event: EventWriter<MetaEvent>
+) {
+    if !player.playing || !player.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let next = player.position + 1;
+    go_to(&mut commands, &marks, &mut game_state, &mut player, next, &mut draw_queue, &mut highlight_queue);
+    player.playing = player.position < player.total();
+    player.wait_for_next();
+    meta_event.send(player.status());
+}
+
+fn replay_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut controls: EventWriter<ReplayControl>
+) {
+    let bindings = [
+        (KeyCode::Home, ReplayControl::First),
+        (KeyCode::ArrowLeft, ReplayControl::Back),
+        (KeyCode::Space, ReplayControl::TogglePlay),
+        (KeyCode::ArrowRight, ReplayControl::Forward),
+        (KeyCode::End, ReplayControl::Last)
+    ];
+    for (key, control) in bindings {
+        if keys.just_pressed(key) {
+            controls.send(control);
+        }
+    }
+}
+
+/// Shows the board after the first `target` moves. One move forward is
+/// simply drawn; anything else redraws the board from scratch.
+fn go_to(
+    commands: &mut Commands,
+    marks: &Query<Entity, With<Mark>>,
+    game_state: &mut GameState,
+    player: &mut ReplayPlayer,
+    target: usize,
+    draw_queue: &mut EventWriter<DrawRequest>,
+    highlight_queue: &mut EventWriter<HighlightLine>
+) {
+    let target = target.min(player.total());
+    if target == player.position + 1 {
+        let (mv, _) = player.moves[player.position];
+        if game_state.board.apply_move(mv).is_ok() {
+            draw_queue.send(DrawRequest { piece: mv.piece, where_: mv.cell });
+        }
+    } else if target != player.position {
+        for mark in marks.iter() {
+            commands.entity(mark).despawn_recursive();
+        }
+        let mut board = Game::new(player.variant);
+        for (mv, _) in &player.moves[..target] {
+            board.apply_move(*mv).ok();
+        }
+        for (cell, state) in board.cells().iter().enumerate() {
+            if let Some(piece) = piece_on(*state) {
+                draw_queue.send(DrawRequest { piece, where_: cell });
+            }
+        }
+        game_state.board = board;
+    } else {
+        return;
+    }
+    player.position = target;
+    if let Some((_, line)) = game_state.board.winning_line() {
+        highlight_queue.send(HighlightLine { cells: line.cells().to_vec() });
+    }
+}
@@ -0,0 +1,644 @@
+use crate::board::{Board, Cell, Move, Outcome, Piece, Rules, MAX_CELLS};
+use crate::game::Game;
+use crate::ultimate::{UltimateBoard, CLASSIC_LINES, SUB_BOARDS};
+use crate::Player;
+
+const CORNERS: [usize; 4] = [0, 2, 6, 8];
+
+// Boards up to this many cells are searched to the end, anything
+// bigger only this many plies deep, scored by `evaluate`.
+const FULL_SEARCH_CELLS: usize = 9;
+const VARIANT_DEPTH: u32 = 2;
+const ULTIMATE_DEPTH: u32 = 4;
+// Each free cell takes either mark in wild and any unused number in
+// numerical, too many moves to search those to the end.
+const WILD_DEPTH: u32 = 4;
+const NUMERICAL_DEPTH: u32 = 3;
+
+// Cells the depth-limited searches may look at, counting every cell of
+// every position visited, before settling for the deepest search they
+// finished. Keeps the browser, which thinks on its only thread, responsive.
+const SEARCH_BUDGET: u32 = 2_000_000;
+
+// A claimed sub-board outweighs anything going on inside the open ones.
+const SUB_BOARD_WEIGHT: i32 = 64;
+
+// Above anything `evaluate` can return, so a real win always beats a good-looking position.
+const WIN_SCORE: i32 = 1 << 24;
+
+/// How hard the computer tries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    /// Any legal move.
+    Random,
+    /// Wins or blocks when it can, otherwise prefers the center and corners.
+    /// In misère, wild and numerical it only avoids handing over a win.
+    Heuristic,
+    /// Minimax with alpha-beta pruning. Never loses on the classic board,
+    /// looks a few moves ahead on bigger ones.
+    Perfect
+}
+
+/// Picks a move for whoever's turn it is in `game`, or `None` once the
+/// game is over. `pick(n)` must return a number below `n` and is used to
+/// choose between equally good moves, so the bot doesn't always play alike.
+pub fn choose_move(game: &Game, difficulty: Difficulty, pick: &mut impl FnMut(usize) -> usize) -> Option<Move> {
+    if game.outcome().is_some() {
+        return None;
+    }
+
+    let turn = game.turn();
+    let mut candidates = [Move::new(0, turn); MAX_CELLS];
+    let count = match difficulty {
+        Difficulty::Random => legal_moves(game, &mut candidates),
+        Difficulty::Heuristic => heuristic_moves(game, &mut candidates),
+        Difficulty::Perfect => best_moves(game, &mut candidates)
+    };
+    match count {
+        0 => None,
+        count => Some(candidates[pick(count) % count])
+    }
+}
+
+fn heuristic_moves(game: &Game, out: &mut [Move; MAX_CELLS]) -> usize {
+    let turn = game.turn();
+    match game {
+        Game::Standard(board) => marking(turn, out, |cells| heuristic_cells(board, cells)),
+        Game::Ultimate(board) => marking(turn, out, |cells| ultimate_heuristic_cells(board, cells)),
+        _ => cautious_moves(game, out)
+    }
+}
+
+fn legal_moves(game: &Game, out: &mut [Move; MAX_CELLS]) -> usize {
+    let turn = game.turn();
+    match game {
+        Game::Standard(board) | Game::Misere(board) => marking(turn, out, |cells| free_cells(board, cells)),
+        Game::Ultimate(board) => marking(turn, out, |cells| ultimate_cells(board, cells)),
+        Game::Wild(_) | Game::Numerical(_) => {
+            let pieces = [Piece::Mark(Player::X), Piece::Mark(Player::O)].into_iter().chain((1..=9).map(Piece::Number));
+            let mut count = 0;
+            for cell in 0..game.cells().len() {
+                for piece in pieces.clone() {
+                    let mv = Move { cell, player: turn, piece };
+                    if game.validate_move(mv).is_ok() {
+                        out[count] = mv;
+                        count += 1;
+                    }
+                }
+            }
+            count
+        }
+    }
+}
+
+/// `player` putting their own mark in each of the cells `cells_of` picks.
+fn marking(player: Player, out: &mut [Move; MAX_CELLS], cells_of: impl FnOnce(&mut [usize; MAX_CELLS]) -> usize) -> usize {
+    let mut cells = [0; MAX_CELLS];
+    let count = cells_of(&mut cells);
+    for (mv, cell) in out.iter_mut().zip(&cells[..count]) {
+        *mv = Move::new(*cell, player);
+    }
+    count
+}
+
+fn ultimate_cells(board: &UltimateBoard, out: &mut [usize; MAX_CELLS]) -> usize {
+    let mut count = 0;
+    for (cell, state) in board.cells().iter().enumerate() {
+        if *state == Cell::Empty && board.is_playable(UltimateBoard::split(cell).0) {
+            out[count] = cell;
+            count += 1;
+        }
+    }
+    count
+}
+
+fn free_cells(board: &Board, out: &mut [usize; MAX_CELLS]) -> usize {
+    let mut count = 0;
+    for (cell, state) in board.cells().iter().enumerate() {
+        if *state == Cell::Empty {
+            out[count] = cell;
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Free cells next to a mark, where anything interesting on a big board happens.
+/// The center when the board is still empty.
+fn nearby_cells(board: &Board, out: &mut [usize; MAX_CELLS]) -> usize {
+    let size = board.rules().size() as isize;
+    let mut free = [0; MAX_CELLS];
+    let free_count = free_cells(board, &mut free);
+    let count = collect(&free[..free_count], out, |cell| {
+        let (row, col) = (cell as isize / size, cell as isize % size);
+        (-1..=1).any(|dr| (-1..=1).any(|dc| {
+            let (r, c) = (row + dr, col + dc);
+            (0..size).contains(&r) && (0..size).contains(&c)
+                && board.cell((r * size + c) as usize) != Some(Cell::Empty)
+        }))
+    });
+    if count > 0 {
+        return count;
+    }
+    out[0] = center(board.rules());
+    1
+}
+
+fn heuristic_cells(board: &Board, out: &mut [usize; MAX_CELLS]) -> usize {
+    let turn = board.turn();
+    let mut free = [0; MAX_CELLS];
+    let free_count = free_cells(board, &mut free);
+    let free = &free[..free_count];
+
+    // Our own win first, then whatever stops theirs.
+    for player in [turn, turn.opposite()] {
+        let count = collect(free, out, |cell| board.would_win(cell, player));
+        if count > 0 {
+            return count;
+        }
+    }
+
+    let center = center(board.rules());
+    if free.contains(&center) {
+        out[0] = center;
+        return 1;
+    }
+    if board.rules() == Rules::CLASSIC {
+        let count = collect(free, out, |cell| CORNERS.contains(&cell));
+        if count > 0 {
+            return count;
+        }
+        return collect(free, out, |_| true);
+    }
+    nearby_cells(board, out)
+}
+
+/// Ultimate's take on the same idea: win the game, else a sub-board,
+/// else keep the opponent from taking one, and rather not send them
+/// anywhere they could take one next.
+fn ultimate_heuristic_cells(board: &UltimateBoard, out: &mut [usize; MAX_CELLS]) -> usize {
+    let turn = board.turn();
+    let mut legal = [0; MAX_CELLS];
+    let legal_count = ultimate_cells(board, &mut legal);
+    let legal = &legal[..legal_count];
+
+    let after = |cell: usize| {
+        let mut next = *board;
+        next.apply_move(Move::new(cell, turn)).ok().map(|outcome| (next, outcome))
+    };
+    let steps: [&dyn Fn(usize) -> bool; 4] = [
+        &|cell| matches!(after(cell), Some((_, Some(Outcome::Win(_))))),
+        &|cell| claims_sub_board(board, cell, turn),
+        &|cell| claims_sub_board(board, cell, turn.opposite()),
+        &|cell| after(cell).is_some_and(|(next, _)| {
+            let mut replies = [0; MAX_CELLS];
+            let reply_count = ultimate_cells(&next, &mut replies);
+            !replies[..reply_count].iter().any(|reply| claims_sub_board(&next, *reply, turn.opposite()))
+        })
+    ];
+    for keep in steps {
+        let count = collect(legal, out, keep);
+        if count > 0 {
+            return count;
+        }
+    }
+    collect(legal, out, |_| true)
+}
+
+/// Whether `player` marking the empty `cell` would win its sub-board.
+fn claims_sub_board(board: &UltimateBoard, cell: usize, player: Player) -> bool {
+    let (sub_board, local) = UltimateBoard::split(cell);
+    let first = sub_board * SUB_BOARDS;
+    board.claimed_by(sub_board).is_none()
+        && board.cell(cell) == Some(Cell::Empty)
+        && CLASSIC_LINES.iter().filter(|line| line.contains(&local)).any(|line| {
+            line.iter().all(|other| *other == local || board.cell(first + other) == Some(Cell::Taken(player)))
+        })
+}
+
+/// For the variants where a line can't simply be blocked: win right
+/// away if possible, else play something that neither loses on the spot
+/// nor lets the opponent win with their reply.
+fn cautious_moves(game: &Game, out: &mut [Move; MAX_CELLS]) -> usize {
+    let turn = game.turn();
+    let mut legal = [Move::new(0, turn); MAX_CELLS];
+    let legal_count = legal_moves(game, &mut legal);
+    let legal = &legal[..legal_count];
+
+    let after = |mv: Move| {
+        let mut next = *game;
+        next.apply_move(mv).ok().map(|outcome| (next, outcome))
+    };
+    let loses = |mv: Move| matches!(after(mv), Some((_, Some(Outcome::Win(winner)))) if winner != turn);
+    let steps: [&dyn Fn(Move) -> bool; 3] = [
+        &|mv| matches!(after(mv), Some((_, Some(Outcome::Win(winner)))) if winner == turn),
+        &|mv| !loses(mv) && after(mv).is_some_and(|(next, _)| {
+            let mut replies = [Move::new(0, turn); MAX_CELLS];
+            let reply_count = legal_moves(&next, &mut replies);
+            !replies[..reply_count].iter().any(|reply| {
+                let mut last = next;
+                matches!(last.apply_move(*reply), Ok(Some(Outcome::Win(winner))) if winner != turn)
+            })
+        }),
+        &|mv| !loses(mv)
+    ];
+    for keep in steps {
+        let count = collect(legal, out, keep);
+        if count > 0 {
+            return count;
+        }
+    }
+    collect(legal, out, |_| true)
+}
+
+/// Searches one ply deeper at a time, so running out of budget still
+/// leaves the last search that finished, or the heuristic's wins and
+/// blocks if not even the first did. Boards searched to the end are
+/// small enough to always finish, and have to for the bot never to lose.
+fn best_moves(game: &Game, out: &mut [Move; MAX_CELLS]) -> usize {
+    let turn = game.turn();
+    let mut moves = [Move::new(0, turn); MAX_CELLS];
+    let move_count = candidate_moves(game, &mut moves);
+    let moves = &moves[..move_count];
+
+    let full_depth = search_depth(game);
+    // Two plies at least, or the bot wouldn't see the opponent's win coming.
+    let (first_depth, mut budget) = if searched_to_the_end(game) { (full_depth, u32::MAX) } else { (full_depth.min(2), SEARCH_BUDGET) };
+    let mut scores = [i32::MIN; MAX_CELLS];
+    for depth in first_depth..=full_depth {
+        let mut deeper = [i32::MIN; MAX_CELLS];
+        // Only moves at least as good as the best so far need an exact
+        // score, to pick between equally good ones.
+        let mut best = -2 * WIN_SCORE;
+        for (score, mv) in deeper.iter_mut().zip(moves) {
+            let mut next = *game;
+            *score = match next.apply_move(*mv) {
+                Ok(outcome) => score_after(&next, turn, outcome, depth - 1, best - 1, 2 * WIN_SCORE, &mut budget),
+                Err(_) => continue
+            };
+            best = best.max(*score);
+        }
+        // Moves the search didn't get to before the budget ran out all
+        // score 0, so they can't be told apart from an even position.
+        if budget == 0 {
+            if depth == first_depth {
+                return heuristic_moves(game, out);
+            }
+            break;
+        }
+        scores = deeper;
+    }
+
+    let best = scores.iter().copied().max().unwrap_or(i32::MIN);
+    let mut count = 0;
+    for (score, mv) in scores.iter().zip(moves) {
+        if *score == best {
+            out[count] = *mv;
+            count += 1;
+        }
+    }
+    count
+}
+
+fn searched_to_the_end(game: &Game) -> bool {
+    matches!(game, Game::Standard(board) | Game::Misere(board) if board.rules().cell_count() <= FULL_SEARCH_CELLS)
+}
+
+fn search_depth(game: &Game) -> u32 {
+    match game {
+        Game::Standard(board) if board.rules().cell_count() <= FULL_SEARCH_CELLS => board.rules().cell_count() as u32,
+        Game::Standard(_) => VARIANT_DEPTH,
+        Game::Ultimate(_) => ULTIMATE_DEPTH,
+        Game::Misere(board) => board.rules().cell_count() as u32,
+        Game::Wild(_) => WILD_DEPTH,
+        Game::Numerical(_) => NUMERICAL_DEPTH
+    }
+}
+
+fn candidate_moves(game: &Game, out: &mut [Move; MAX_CELLS]) -> usize {
+    match game {
+        Game::Standard(board) if board.rules().cell_count() > FULL_SEARCH_CELLS => {
+            marking(board.turn(), out, |cells| nearby_cells(board, cells))
+        },
+        _ => legal_moves(game, out)
+    }
+}
+
+/// Value of the position for `mover`, who just moved into it. Exact
+/// between `alpha` and `beta`, and only a bound outside them.
+fn score_after(game: &Game, mover: Player, outcome: Option<Outcome>, depth: u32, alpha: i32, beta: i32, budget: &mut u32) -> i32 {
+    match outcome {
+        // Sooner wins score higher, so the bot doesn't toy with its opponent.
+        Some(Outcome::Win(winner)) if winner == mover => WIN_SCORE + empty_cells(game) as i32,
+        // Only in misère can a move lose outright, and later is better then.
+        Some(Outcome::Win(_)) => -WIN_SCORE - empty_cells(game) as i32,
+        Some(Outcome::Draw) => 0,
+        None => -negamax(game, depth, -beta, -alpha, budget)
+    }
+}
+
+/// Value of `game` for the side to move, searched with alpha-beta pruning.
+/// Meaningless once `budget` runs out, so callers have to check it.
+fn negamax(game: &Game, depth: u32, mut alpha: i32, beta: i32, budget: &mut u32) -> i32 {
+    if *budget == 0 {
+        return 0;
+    }
+    *budget = budget.saturating_sub(game.cells().len() as u32);
+    let turn = game.turn();
+    if depth == 0 {
+        return match game {
+            Game::Standard(board) => evaluate(board, turn),
+            Game::Ultimate(board) => evaluate_ultimate(board, turn),
+            // Nothing to go on short of a finished line.
+            Game::Misere(_) | Game::Wild(_) | Game::Numerical(_) => 0
+        };
+    }
+
+    let mut moves = [Move::new(0, turn); MAX_CELLS];
+    let move_count = candidate_moves(game, &mut moves);
+    let mut best = i32::MIN;
+    for mv in &moves[..move_count] {
+        let mut next = *game;
+        let score = match next.apply_move(*mv) {
+            Ok(outcome) => score_after(&next, turn, outcome, depth - 1, alpha, beta, budget),
+            Err(_) => continue
+        };
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// Rough value of an unfinished position for `player`: every stretch of
+/// `win_length` cells that only one side has marks in counts for that
+/// side, and counts for a lot more the fuller it is.
+fn evaluate(board: &Board, player: Player) -> i32 {
+    let rules = board.rules();
+    let (size, k) = (rules.size() as isize, rules.win_length() as isize);
+    let mut score = 0;
+
+    for (dr, dc) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+        for row in 0..size {
+            for col in 0..size {
+                let (end_r, end_c) = (row + dr * (k - 1), col + dc * (k - 1));
+                if !(0..size).contains(&end_r) || !(0..size).contains(&end_c) {
+                    continue;
+                }
+                let (mut mine, mut theirs) = (0, 0);
+                for step in 0..k {
+                    match board.cell(((row + dr * step) * size + col + dc * step) as usize) {
+                        Some(Cell::Taken(owner)) if owner == player => mine += 1,
+                        Some(Cell::Taken(_)) => theirs += 1,
+                        _ => {}
+                    }
+                }
+                score += stretch_score(mine, theirs);
+            }
+        }
+    }
+    score
+}
+
+/// The same for ultimate, once over the lines inside every open
+/// sub-board and once, weighted far heavier, over the big board.
+fn evaluate_ultimate(board: &UltimateBoard, player: Player) -> i32 {
+    let mut score = 0;
+    for sub_board in (0..SUB_BOARDS).filter(|sub_board| !board.is_decided(*sub_board)) {
+        let first = sub_board * SUB_BOARDS;
+        for line in CLASSIC_LINES {
+            let (mut mine, mut theirs) = (0, 0);
+            for cell in line {
+                match board.cell(first + cell) {
+                    Some(Cell::Taken(owner)) if owner == player => mine += 1,
+                    Some(Cell::Taken(_)) => theirs += 1,
+                    _ => {}
+                }
+            }
+            score += stretch_score(mine, theirs);
+        }
+    }
+    for line in CLASSIC_LINES {
+        let (mut mine, mut theirs) = (0, 0);
+        for sub_board in line {
+            match board.claimed_by(sub_board) {
+                Some(owner) if owner == player => mine += 1,
+                Some(_) => theirs += 1,
+                // A drawn sub-board blocks the line for both sides.
+                None if board.is_decided(sub_board) => {
+                    mine += 1;
+                    theirs += 1;
+                },
+                None => {}
+            }
+        }
+        score += SUB_BOARD_WEIGHT * stretch_score(mine, theirs);
+    }
+    score
+}
+
+fn stretch_score(mine: u32, theirs: u32) -> i32 {
+    match (mine, theirs) {
+        (mine, 0) if mine > 0 => 1 << (2 * mine),
+        (0, theirs) if theirs > 0 => -(1 << (2 * theirs)),
+        _ => 0
+    }
+}
+
+fn center(rules: Rules) -> usize {
+    let middle = rules.size() / 2;
+    middle * rules.size() + middle
+}
+
+fn empty_cells(game: &Game) -> usize {
+    game.cells().iter().filter(|cell| **cell == Cell::Empty).count()
+}
+
+fn collect<T: Copy>(items: &[T], out: &mut [T; MAX_CELLS], keep: impl Fn(T) -> bool) -> usize {
+    let mut count = 0;
+    for &item in items {
+        if keep(item) {
+            out[count] = item;
+            count += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variant;
+
+    /// Plays every possible line of the opponent against the perfect bot.
+    fn never_loses(board: Board, bot: Player) {
+        if board.outcome().is_some() {
+            assert_ne!(board.outcome(), Some(Outcome::Win(bot.opposite())), "lost: {board:?}");
+            return;
+        }
+
+        if board.turn() == bot {
+            // Every equally good reply has to hold up, not just the first one.
+            let mut candidates = [Move::new(0, bot); MAX_CELLS];
+            let count = best_moves(&board.into(), &mut candidates);
+            assert!(count > 0);
+            for mv in &candidates[..count] {
+                let mut next = board;
+                next.apply_move(*mv).unwrap();
+                never_loses(next, bot);
+            }
+        } else {
+            for cell in 0..board.rules().cell_count() {
+                let mut next = board;
+                if next.apply_move(Move::new(cell, bot.opposite())).is_ok() {
+                    never_loses(next, bot);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn perfect_never_loses() {
+        never_loses(Board::default(), Player::X);
+        never_loses(Board::default(), Player::O);
+    }
+
+    #[test]
+    fn heuristic_wins_then_blocks() {
+        let x = Cell::Taken(Player::X);
+        let o = Cell::Taken(Player::O);
+        let e = Cell::Empty;
+
+        // X to move can win on 2 while O threatens 5.
+        let board = Board::from_cells(Rules::CLASSIC, &[x, x, e, o, o, e, e, e, e]).unwrap();
+        assert_eq!(choose_move(&board.into(), Difficulty::Heuristic, &mut |_| 0), Some(Move::new(2, Player::X)));
+
+        // O to move has no win of its own and must block 2.
+        let board = Board::from_cells(Rules::CLASSIC, &[x, x, e, o, e, e, e, e, e]).unwrap();
+        assert_eq!(choose_move(&board.into(), Difficulty::Heuristic, &mut |_| 0), Some(Move::new(2, Player::O)));
+    }
+
+    #[test]
+    fn no_move_once_over() {
+        let x = Cell::Taken(Player::X);
+        let o = Cell::Taken(Player::O);
+        let e = Cell::Empty;
+        let board = Board::from_cells(Rules::CLASSIC, &[x, x, x, o, o, e, e, e, e]).unwrap();
+        for difficulty in [Difficulty::Random, Difficulty::Heuristic, Difficulty::Perfect] {
+            assert_eq!(choose_move(&board.into(), difficulty, &mut |_| 0), None);
+        }
+    }
+
+    #[test]
+    fn perfect_blocks_on_big_boards() {
+        let rules = Rules::new(15, 5).unwrap();
+        let mut board = Board::new(rules);
+        // X has four in a row on the top row, open at cell 4, and O must take it.
+        for (x, o) in [(0, 100), (1, 101), (2, 102), (3, 120)] {
+            board.apply_move(Move::new(x, Player::X)).unwrap();
+            board.apply_move(Move::new(o, Player::O)).unwrap();
+        }
+        board.apply_move(Move::new(200, Player::X)).unwrap();
+        assert_eq!(choose_move(&board.into(), Difficulty::Perfect, &mut |_| 0), Some(Move::new(4, Player::O)));
+        assert_eq!(choose_move(&board.into(), Difficulty::Heuristic, &mut |_| 0), Some(Move::new(4, Player::O)));
+    }
+
+    #[test]
+    fn perfect_still_blocks_past_the_budget() {
+        let rules = Rules::new(15, 5).unwrap();
+        let mut board = Board::new(rules);
+        // Marks scattered all over leave too many replies to search two plies deep.
+        let scattered = (60..rules.cell_count()).filter(|cell| cell / 15 % 3 == 1 && cell % 15 % 3 == 1);
+        // Then X gets four in a row at the end of the bottom row, open only
+        // at 220, far down the list of candidates, and O must take it.
+        let opening = [221, 0, 222, 2, 223, 30, 224];
+        for cell in scattered.chain(opening) {
+            let player = board.turn();
+            board.apply_move(Move::new(cell, player)).unwrap();
+        }
+        let game = Game::from(board);
+
+        let mut candidates = [Move::new(0, Player::O); MAX_CELLS];
+        let count = best_moves(&game, &mut candidates);
+        assert!(count > 0);
+        assert!(candidates[..count].iter().all(|mv| mv.cell == 220), "{:?}", &candidates[..count]);
+        for pick in [|n: usize| n - 1, |n: usize| n / 2] {
+            assert_eq!(choose_move(&game, Difficulty::Perfect, &mut { pick }), Some(Move::new(220, Player::O)));
+        }
+    }
+
+    #[test]
+    fn bots_play_whole_games() {
+        let variants = [Variant::Ultimate, Variant::Misere, Variant::Wild, Variant::Numerical];
+        for (variant, difficulty) in variants.into_iter().flat_map(|variant| {
+            [Difficulty::Random, Difficulty::Heuristic, Difficulty::Perfect].map(|difficulty| (variant, difficulty))
+        }) {
+            let mut game = Game::new(variant);
+            let mut seed = 7;
+            let mut pick = |n: usize| {
+                seed = (seed * 1103515245 + 12345) % (1 << 31);
+                seed % n
+            };
+            while let Some(mv) = choose_move(&game, difficulty, &mut pick) {
+                game.apply_move(mv).unwrap();
+            }
+            assert!(game.outcome().is_some());
+        }
+    }
+
+    #[test]
+    fn ultimate_takes_the_sub_board() {
+        let mut board = UltimateBoard::new();
+        // X holds cells 0 and 1 of sub-board 0, and O just sent it back there.
+        for cell in [1, 9, 0, 3, 31, 36] {
+            let player = board.turn();
+            board.apply_move(Move::new(cell, player)).unwrap();
+        }
+        assert_eq!(board.forced_board(), Some(0));
+        for difficulty in [Difficulty::Heuristic, Difficulty::Perfect] {
+            assert_eq!(choose_move(&Game::Ultimate(board), difficulty, &mut |_| 0), Some(Move::new(2, Player::X)));
+        }
+    }
+
+    /// Plays `moves` from the start of `variant`, each by whoever's turn it is.
+    fn play(variant: Variant, moves: &[(usize, Piece)]) -> Game {
+        let mut game = Game::new(variant);
+        for &(cell, piece) in moves {
+            let player = game.turn();
+            game.apply_move(Move { cell, player, piece }).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn misere_leaves_the_line_open() {
+        // X would lose by completing the top row on 2.
+        let (x, o) = (Piece::Mark(Player::X), Piece::Mark(Player::O));
+        let game = play(Variant::Misere, &[(0, x), (3, o), (1, x), (4, o)]);
+        for difficulty in [Difficulty::Heuristic, Difficulty::Perfect] {
+            for choice in 0..6 {
+                let mv = choose_move(&game, difficulty, &mut |_| choice).unwrap();
+                assert_ne!(mv.cell, 2, "{difficulty:?} completed its own line");
+            }
+        }
+    }
+
+    #[test]
+    fn wild_and_numerical_take_the_win() {
+        // X can finish the top row with an O.
+        let wild = play(Variant::Wild, &[(0, Piece::Mark(Player::O)), (4, Piece::Mark(Player::X)),
+            (1, Piece::Mark(Player::O)), (8, Piece::Mark(Player::X))]);
+        // X can finish the top row with 1 + 5 + 9.
+        let numerical = play(Variant::Numerical, &[(0, Piece::Number(1)), (4, Piece::Number(2)),
+            (1, Piece::Number(5)), (8, Piece::Number(4))]);
+        let wins = [(wild, Piece::Mark(Player::O)), (numerical, Piece::Number(9))];
+        for (game, piece) in wins {
+            for difficulty in [Difficulty::Heuristic, Difficulty::Perfect] {
+                let expected = Move { cell: 2, player: Player::X, piece };
+                assert_eq!(choose_move(&game, difficulty, &mut |_| 0), Some(expected), "{:?}", game.variant());
+            }
+        }
+    }
+}
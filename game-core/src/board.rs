@@ -0,0 +1,404 @@
+use crate::Player;
+
+/// Largest supported side, enough for fifteen-by-fifteen five-in-a-row.
+pub const MAX_SIZE: usize = 15;
+pub const MAX_CELLS: usize = MAX_SIZE * MAX_SIZE;
+
+// Right, down, down-right and down-left; each line is walked both ways.
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// Side of the square board and how many marks in a row win.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rules {
+    size: usize,
+    win_length: usize
+}
+
+impl Rules {
+    pub const CLASSIC: Rules = Rules { size: 3, win_length: 3 };
+
+    /// `None` unless `3 <= win_length <= size <= MAX_SIZE`.
+    pub fn new(size: usize, win_length: usize) -> Option<Self> {
+        (3 <= win_length && win_length <= size && size <= MAX_SIZE).then_some(Rules { size, win_length })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn win_length(&self) -> usize {
+        self.win_length
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.size * self.size
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules::CLASSIC
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Cell {
+    #[default]
+    Empty,
+    Taken(Player),
+    /// Numerical only: the number written in the cell.
+    Number(u8)
+}
+
+/// What a move puts down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Piece {
+    Mark(Player),
+    /// Numerical only.
+    Number(u8)
+}
+
+impl From<Piece> for Cell {
+    fn from(piece: Piece) -> Self {
+        match piece {
+            Piece::Mark(player) => Cell::Taken(player),
+            Piece::Number(number) => Cell::Number(number)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub cell: usize,
+    pub player: Player,
+    /// The mover's own mark, except in the wild and numerical variants.
+    pub piece: Piece
+}
+
+impl Move {
+    /// `player` putting their own mark in `cell`.
+    pub fn new(cell: usize, player: Player) -> Self {
+        Move { cell, player, piece: Piece::Mark(player) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win(Player),
+    Draw
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    OutOfBounds,
+    CellOccupied,
+    NotYourTurn,
+    GameOver,
+    /// Ultimate only: the move is outside the sub-board the last move sent us to.
+    WrongSubBoard,
+    /// Not a piece the mover may put down in this variant.
+    WrongPiece,
+    /// Numerical only: that number is already on the board.
+    NumberUsed
+}
+
+/// Cells of a completed line, in order along the line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Line {
+    cells: [usize; MAX_SIZE],
+    len: usize
+}
+
+impl Line {
+    pub(crate) fn from_cells(cells: &[usize]) -> Self {
+        let mut line = Line { cells: [0; MAX_SIZE], len: cells.len() };
+        line.cells[..cells.len()].copy_from_slice(cells);
+        line
+    }
+
+    pub fn cells(&self) -> &[usize] {
+        &self.cells[..self.len]
+    }
+}
+
+/// Square board of any size up to `MAX_SIZE`, cells numbered row by
+/// row. X always moves first, so whose turn it is follows from how many
+/// marks each side has placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Board {
+    rules: Rules,
+    cells: [Cell; MAX_CELLS],
+    // Found when the line is completed, so later checks are free.
+    winner: Option<(Player, Line)>
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::new(Rules::CLASSIC)
+    }
+}
+
+impl Board {
+    pub fn new(rules: Rules) -> Self {
+        Self { rules, cells: [Cell::Empty; MAX_CELLS], winner: None }
+    }
+
+    /// A board with the given cells, or `None` if there aren't exactly
+    /// as many as the rules call for or some hold numbers.
+    pub fn from_cells(rules: Rules, cells: &[Cell]) -> Option<Self> {
+        if cells.len() != rules.cell_count() || cells.iter().any(|cell| matches!(cell, Cell::Number(_))) {
+            return None;
+        }
+        let mut board = Board::new(rules);
+        board.cells[..cells.len()].copy_from_slice(cells);
+        board.winner = (0..cells.len()).find_map(|cell| board.line_through(cell));
+        Some(board)
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells[..self.rules.cell_count()]
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        self.cells().get(index).copied()
+    }
+
+    pub fn turn(&self) -> Player {
+        let xs = self.count(Player::X);
+        let os = self.count(Player::O);
+
+        if xs == os {
+            Player::X
+        } else {
+            Player::O
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.cells().iter().all(|cell| *cell != Cell::Empty)
+    }
+
+    /// The completed line on the board and who owns it.
+    pub fn winning_line(&self) -> Option<(Player, Line)> {
+        self.winner
+    }
+
+    /// Whether `player` marking the empty `cell` would complete a line.
+    pub fn would_win(&self, cell: usize, player: Player) -> bool {
+        if self.cell(cell) != Some(Cell::Empty) {
+            return false;
+        }
+        let mut next = *self;
+        next.cells[cell] = Cell::Taken(player);
+        next.line_through(cell).is_some()
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some((player, _)) = self.winner {
+            return Some(Outcome::Win(player));
+        }
+
+        if self.is_full() {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate_move(&self, mv: Move) -> Result<(), MoveError> {
+        if self.outcome().is_some() {
+            return Err(MoveError::GameOver);
+        }
+        match self.cell(mv.cell) {
+            None => Err(MoveError::OutOfBounds),
+            Some(Cell::Taken(_) | Cell::Number(_)) => Err(MoveError::CellOccupied),
+            Some(Cell::Empty) if mv.player != self.turn() => Err(MoveError::NotYourTurn),
+            Some(Cell::Empty) if mv.piece != Piece::Mark(mv.player) => Err(MoveError::WrongPiece),
+            Some(Cell::Empty) => Ok(())
+        }
+    }
+
+    /// Places the mark if the move is legal and returns the outcome
+    /// if this move ended the game. Only lines through the new mark
+    /// are checked, since any other line was already there before.
+    pub fn apply_move(&mut self, mv: Move) -> Result<Option<Outcome>, MoveError> {
+        self.validate_move(mv)?;
+        self.cells[mv.cell] = Cell::Taken(mv.player);
+        self.winner = self.line_through(mv.cell);
+        Ok(self.outcome())
+    }
+
+    /// The first run of at least `win_length` marks through `cell`.
+    fn line_through(&self, cell: usize) -> Option<(Player, Line)> {
+        let Cell::Taken(player) = self.cells[cell] else {
+            return None;
+        };
+        let size = self.rules.size as isize;
+        let (row, col) = ((cell / self.rules.size) as isize, (cell % self.rules.size) as isize);
+        let owned = |r: isize, c: isize| {
+            (0..size).contains(&r) && (0..size).contains(&c)
+                && self.cells[(r * size + c) as usize] == Cell::Taken(player)
+        };
+
+        for (dr, dc) in DIRECTIONS {
+            // Back up to the start of the run, then walk it forwards.
+            let mut start = 0;
+            while owned(row - (start + 1) * dr, col - (start + 1) * dc) {
+                start += 1;
+            }
+            let mut line = Line { cells: [0; MAX_SIZE], len: 0 };
+            let (mut r, mut c) = (row - start * dr, col - start * dc);
+            while owned(r, c) {
+                line.cells[line.len] = (r * size + c) as usize;
+                line.len += 1;
+                r += dr;
+                c += dc;
+            }
+            if line.len >= self.rules.win_length {
+                return Some((player, line));
+            }
+        }
+        None
+    }
+
+    fn count(&self, player: Player) -> usize {
+        self.cells().iter().filter(|cell| **cell == Cell::Taken(player)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Census {
+        positions: HashSet<Board>,
+        games: usize,
+        x_wins: usize,
+        o_wins: usize,
+        draws: usize
+    }
+
+    /// Walks every legal game from `board`, checking the invariants of
+    /// `apply_move` at each reachable position along the way.
+    fn explore(board: Board, census: &mut Census) {
+        census.positions.insert(board);
+        let turn = board.turn();
+
+        for cell in 0..board.rules.cell_count() {
+            let mut next = board;
+            let result = next.apply_move(Move::new(cell, turn));
+
+            if board.cells[cell] != Cell::Empty {
+                assert_eq!(result, Err(MoveError::CellOccupied));
+                assert_eq!(next, board);
+                continue;
+            }
+
+            let wrong_side = board.validate_move(Move::new(cell, turn.opposite()));
+            assert_eq!(wrong_side, Err(MoveError::NotYourTurn));
+
+            assert_eq!(next.cell(cell), Some(Cell::Taken(turn)));
+            assert_eq!(next.turn(), turn.opposite());
+            match result {
+                Ok(None) => explore(next, census),
+                Ok(Some(outcome)) => {
+                    census.positions.insert(next);
+                    census.games += 1;
+                    match outcome {
+                        Outcome::Win(player) => {
+                            assert_eq!(player, turn, "only the mover can complete a line");
+                            let (_, line) = next.winning_line().unwrap();
+                            assert!(line.cells().contains(&cell), "the last move must be part of the line");
+                            match player {
+                                Player::X => census.x_wins += 1,
+                                Player::O => census.o_wins += 1
+                            }
+                        },
+                        Outcome::Draw => {
+                            assert!(next.is_full());
+                            census.draws += 1;
+                        }
+                    }
+                    for cell in 0..next.rules.cell_count() {
+                        assert_eq!(
+                            next.validate_move(Move::new(cell, next.turn())),
+                            Err(MoveError::GameOver)
+                        );
+                    }
+                },
+                Err(err) => panic!("legal move rejected: {err:?}")
+            }
+        }
+    }
+
+    #[test]
+    fn every_reachable_position() {
+        let mut census = Census::default();
+        explore(Board::default(), &mut census);
+
+        assert_eq!(census.positions.len(), 5478);
+        assert_eq!(census.games, 255168);
+        assert_eq!(census.x_wins, 131184);
+        assert_eq!(census.o_wins, 77904);
+        assert_eq!(census.draws, 46080);
+    }
+
+    #[test]
+    fn x_moves_first() {
+        let board = Board::default();
+        assert_eq!(board.turn(), Player::X);
+        assert_eq!(board.validate_move(Move::new(4, Player::O)), Err(MoveError::NotYourTurn));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut board = Board::default();
+        assert_eq!(board.apply_move(Move::new(9, Player::X)), Err(MoveError::OutOfBounds));
+        assert_eq!(board, Board::default());
+    }
+
+    #[test]
+    fn five_in_a_row_on_fifteen() {
+        let rules = Rules::new(15, 5).unwrap();
+        let mut board = Board::new(rules);
+        // X builds a diagonal from (3, 9) down-left while O plays along the top row.
+        let xs = [3 * 15 + 9, 4 * 15 + 8, 5 * 15 + 7, 7 * 15 + 5, 6 * 15 + 6];
+        for (turn, x) in xs.iter().enumerate() {
+            let outcome = board.apply_move(Move::new(*x, Player::X)).unwrap();
+            if turn < 4 {
+                assert_eq!(outcome, None);
+                board.apply_move(Move::new(turn, Player::O)).unwrap();
+            } else {
+                assert_eq!(outcome, Some(Outcome::Win(Player::X)));
+            }
+        }
+        let (_, line) = board.winning_line().unwrap();
+        assert_eq!(line.cells(), &[3 * 15 + 9, 4 * 15 + 8, 5 * 15 + 7, 6 * 15 + 6, 7 * 15 + 5]);
+
+        // Lines don't wrap around the edge of the board.
+        let mut board = Board::new(rules);
+        for (turn, x) in [12, 13, 14, 15, 16].iter().enumerate() {
+            assert_eq!(board.apply_move(Move::new(*x, Player::X)).unwrap(), None);
+            if turn < 4 {
+                board.apply_move(Move::new(100 + turn, Player::O)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rules_bounds() {
+        assert_eq!(Rules::new(3, 3), Some(Rules::CLASSIC));
+        assert!(Rules::new(4, 5).is_none());
+        assert!(Rules::new(16, 5).is_none());
+        assert!(Rules::new(3, 2).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use core::fmt;
+
+use crate::board::{Board, Cell, Line, Move, MoveError, Outcome, Rules};
+use crate::numerical::NumericalBoard;
+use crate::ultimate::UltimateBoard;
+use crate::wild::WildBoard;
+use crate::Player;
+
+/// Which game is played, chosen before it starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Variant {
+    /// One square board, some number of marks in a row to win.
+    Standard(Rules),
+    /// Nine classic boards in one, see [`UltimateBoard`].
+    Ultimate,
+    /// Classic board where completing three in a row loses.
+    Misere,
+    /// Classic board where either player may put down either mark, see [`WildBoard`].
+    Wild,
+    /// Classic board played with the numbers 1 to 9, see [`NumericalBoard`].
+    Numerical
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant::Standard(Rules::CLASSIC)
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Standard(rules) => write!(f, "{0}x{0}, {1} in a row", rules.size(), rules.win_length()),
+            Variant::Ultimate => write!(f, "ultimate"),
+            Variant::Misere => write!(f, "misère"),
+            Variant::Wild => write!(f, "wild"),
+            Variant::Numerical => write!(f, "numerical")
+        }
+    }
+}
+
+/// A game of any variant. Cells are numbered the way the variant's
+/// board numbers them, and moves, turns and outcomes work alike for all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Game {
+    Standard(Board),
+    Ultimate(UltimateBoard),
+    /// A classic board, only whoever completes a line loses.
+    Misere(Board),
+    Wild(WildBoard),
+    Numerical(NumericalBoard)
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::Standard(Board::default())
+    }
+}
+
+impl From<Board> for Game {
+    fn from(board: Board) -> Self {
+        Game::Standard(board)
+    }
+}
+
+impl Game {
+    pub fn new(variant: Variant) -> Self {
+        match variant {
+            Variant::Standard(rules) => Game::Standard(Board::new(rules)),
+            Variant::Ultimate => Game::Ultimate(UltimateBoard::new()),
+            Variant::Misere => Game::Misere(Board::default()),
+            Variant::Wild => Game::Wild(WildBoard::new()),
+            Variant::Numerical => Game::Numerical(NumericalBoard::new())
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        match self {
+            Game::Standard(board) => Variant::Standard(board.rules()),
+            Game::Ultimate(_) => Variant::Ultimate,
+            Game::Misere(_) => Variant::Misere,
+            Game::Wild(_) => Variant::Wild,
+            Game::Numerical(_) => Variant::Numerical
+        }
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        match self {
+            Game::Standard(board) | Game::Misere(board) => board.cells(),
+            Game::Ultimate(board) => board.cells(),
+            Game::Wild(board) => board.cells(),
+            Game::Numerical(board) => board.cells()
+        }
+    }
+
+    /// Every move fills exactly one cell, so this is also how many
+    /// moves have been made.
+    pub fn moves_played(&self) -> usize {
+        self.cells().iter().filter(|cell| **cell != Cell::Empty).count()
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        self.cells().get(index).copied()
+    }
+
+    pub fn turn(&self) -> Player {
+        match self {
+            Game::Standard(board) | Game::Misere(board) => board.turn(),
+            Game::Ultimate(board) => board.turn(),
+            Game::Wild(board) => board.turn(),
+            Game::Numerical(board) => board.turn()
+        }
+    }
+
+    /// The sub-board the next move has to go to. Only ultimate games have any.
+    pub fn forced_board(&self) -> Option<usize> {
+        match self {
+            Game::Ultimate(board) => board.forced_board(),
+            _ => None
+        }
+    }
+
+    /// The completed line and who it won the game for. In ultimate
+    /// games the line is made of sub-boards rather than cells.
+    pub fn winning_line(&self) -> Option<(Player, Line)> {
+        match self {
+            Game::Standard(board) => board.winning_line(),
+            Game::Ultimate(board) => board.winning_line(),
+            Game::Misere(board) => board.winning_line().map(|(player, line)| (player.opposite(), line)),
+            Game::Wild(board) => board.winning_line(),
+            Game::Numerical(board) => board.winning_line()
+        }
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        match self {
+            Game::Standard(board) => board.outcome(),
+            Game::Ultimate(board) => board.outcome(),
+            Game::Misere(board) => board.outcome().map(misere),
+            Game::Wild(board) => board.outcome(),
+            Game::Numerical(board) => board.outcome()
+        }
+    }
+
+    pub fn validate_move(&self, mv: Move) -> Result<(), MoveError> {
+        match self {
+            Game::Standard(board) | Game::Misere(board) => board.validate_move(mv),
+            Game::Ultimate(board) => board.validate_move(mv),
+            Game::Wild(board) => board.validate_move(mv),
+            Game::Numerical(board) => board.validate_move(mv)
+        }
+    }
+
+    pub fn apply_move(&mut self, mv: Move) -> Result<Option<Outcome>, MoveError> {
+        match self {
+            Game::Standard(board) => board.apply_move(mv),
+            Game::Ultimate(board) => board.apply_move(mv),
+            Game::Misere(board) => board.apply_move(mv).map(|outcome| outcome.map(misere)),
+            Game::Wild(board) => board.apply_move(mv),
+            Game::Numerical(board) => board.apply_move(mv)
+        }
+    }
+}
+
+// Completing a line in misère hands the win to the other side.
+fn misere(outcome: Outcome) -> Outcome {
+    match outcome {
+        Outcome::Win(player) => Outcome::Win(player.opposite()),
+        Outcome::Draw => Outcome::Draw
+    }
+}
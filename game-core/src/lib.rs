@@ -0,0 +1,16 @@
+#![no_std]
+
+pub mod ai;
+mod board;
+mod game;
+mod numerical;
+mod player;
+mod ultimate;
+mod wild;
+
+pub use board::{Board, Cell, Line, Move, MoveError, Outcome, Piece, Rules, MAX_CELLS, MAX_SIZE};
+pub use game::{Game, Variant};
+pub use numerical::NumericalBoard;
+pub use player::Player;
+pub use ultimate::{UltimateBoard, SUB_BOARDS, ULTIMATE_CELLS};
+pub use wild::WildBoard;
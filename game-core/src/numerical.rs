@@ -0,0 +1,162 @@
+use crate::board::{Cell, Line, Move, MoveError, Outcome, Piece};
+use crate::ultimate::CLASSIC_LINES;
+use crate::Player;
+
+// Three different numbers from 1 to 9 in a line have to add up to this.
+const TARGET_SUM: u8 = 15;
+
+/// Classic board filled with the numbers 1 to 9 instead of marks, each
+/// used at most once. X puts down the odd numbers and O the even ones,
+/// and whoever completes a line adding up to 15 wins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct NumericalBoard {
+    cells: [Cell; 9],
+    // Paired with whoever completed the line; both sides' numbers count.
+    winner: Option<(Player, Line)>
+}
+
+impl NumericalBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Which side a number belongs to: X the odd ones, O the even ones.
+    pub fn owner(number: u8) -> Player {
+        if number % 2 == 1 {
+            Player::X
+        } else {
+            Player::O
+        }
+    }
+
+    /// A board with the given cells, or `None` if there aren't nine, or
+    /// some hold marks, numbers outside 1 to 9 or the same number twice.
+    pub fn from_cells(cells: &[Cell]) -> Option<Self> {
+        if cells.len() != 9 {
+            return None;
+        }
+        let mut board = NumericalBoard::new();
+        for (index, cell) in cells.iter().enumerate() {
+            match *cell {
+                Cell::Empty => {},
+                Cell::Number(number) if (1..=9).contains(&number) && !board.is_used(number) => board.cells[index] = *cell,
+                _ => return None
+            }
+        }
+        // A finished game ends on the winning move, so the last mover won.
+        let last_mover = board.turn().opposite();
+        board.winner = CLASSIC_LINES.iter()
+            .find(|line| board.is_complete(line))
+            .map(|line| (last_mover, Line::from_cells(line)));
+        Some(board)
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        self.cells.get(index).copied()
+    }
+
+    pub fn is_used(&self, number: u8) -> bool {
+        self.cells.contains(&Cell::Number(number))
+    }
+
+    pub fn turn(&self) -> Player {
+        let filled = self.cells.iter().filter(|cell| **cell != Cell::Empty).count();
+        if filled % 2 == 0 {
+            Player::X
+        } else {
+            Player::O
+        }
+    }
+
+    /// The completed line and the player who completed it.
+    pub fn winning_line(&self) -> Option<(Player, Line)> {
+        self.winner
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some((player, _)) = self.winner {
+            return Some(Outcome::Win(player));
+        }
+
+        if self.cells.iter().all(|cell| *cell != Cell::Empty) {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate_move(&self, mv: Move) -> Result<(), MoveError> {
+        if self.outcome().is_some() {
+            return Err(MoveError::GameOver);
+        }
+        match (self.cell(mv.cell), mv.piece) {
+            (None, _) => Err(MoveError::OutOfBounds),
+            (Some(Cell::Taken(_) | Cell::Number(_)), _) => Err(MoveError::CellOccupied),
+            (Some(Cell::Empty), _) if mv.player != self.turn() => Err(MoveError::NotYourTurn),
+            (Some(Cell::Empty), Piece::Number(number)) if !(1..=9).contains(&number) || Self::owner(number) != mv.player => {
+                Err(MoveError::WrongPiece)
+            },
+            (Some(Cell::Empty), Piece::Number(number)) if self.is_used(number) => Err(MoveError::NumberUsed),
+            (Some(Cell::Empty), Piece::Number(_)) => Ok(()),
+            (Some(Cell::Empty), Piece::Mark(_)) => Err(MoveError::WrongPiece)
+        }
+    }
+
+    /// Writes the number down if the move is legal and returns the
+    /// outcome if this move ended the game.
+    pub fn apply_move(&mut self, mv: Move) -> Result<Option<Outcome>, MoveError> {
+        self.validate_move(mv)?;
+        self.cells[mv.cell] = mv.piece.into();
+        self.winner = CLASSIC_LINES.iter()
+            .filter(|line| line.contains(&mv.cell))
+            .find(|line| self.is_complete(line))
+            .map(|line| (mv.player, Line::from_cells(line)));
+        Ok(self.outcome())
+    }
+
+    fn is_complete(&self, line: &[usize; 3]) -> bool {
+        let mut sum = 0;
+        for cell in line {
+            match self.cells[*cell] {
+                Cell::Number(number) => sum += number,
+                _ => return false
+            }
+        }
+        sum == TARGET_SUM
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(cell: usize, player: Player, number: u8) -> Move {
+        Move { cell, player, piece: Piece::Number(number) }
+    }
+
+    #[test]
+    fn fifteen_in_a_line_wins() {
+        let mut board = NumericalBoard::new();
+        assert_eq!(board.apply_move(number(0, Player::X, 9)), Ok(None));
+        assert_eq!(board.apply_move(number(1, Player::O, 2)), Ok(None));
+        assert_eq!(board.apply_move(number(8, Player::X, 1)), Ok(None));
+        assert_eq!(board.apply_move(number(2, Player::O, 3)), Err(MoveError::WrongPiece));
+        assert_eq!(board.apply_move(number(2, Player::O, 2)), Err(MoveError::NumberUsed));
+        // Both sides' numbers count: O finishes the top row with 9 + 2 + 4.
+        assert_eq!(board.apply_move(number(2, Player::O, 4)), Ok(Some(Outcome::Win(Player::O))));
+        assert_eq!(board.winning_line().unwrap().1.cells(), &[0, 1, 2]);
+        assert_eq!(NumericalBoard::from_cells(board.cells()).unwrap().outcome(), Some(Outcome::Win(Player::O)));
+    }
+
+    #[test]
+    fn marks_are_not_numbers() {
+        let board = NumericalBoard::new();
+        assert_eq!(board.validate_move(Move::new(0, Player::X)), Err(MoveError::WrongPiece));
+        assert!(NumericalBoard::from_cells(&[Cell::Number(1), Cell::Number(1), Cell::Empty, Cell::Empty,
+            Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty]).is_none());
+    }
+}
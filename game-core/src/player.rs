@@ -0,0 +1,14 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Player {
+    X,
+    O
+}
+
+impl Player {
+    pub fn opposite(self) -> Self {
+        match self {
+            Player::X => Player::O,
+            Player::O => Player::X
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::board::{Cell, Line, Move, MoveError, Outcome, Piece};
+use crate::Player;
+
+/// Sub-boards in the big board, and cells in each sub-board.
+pub const SUB_BOARDS: usize = 9;
+pub const ULTIMATE_CELLS: usize = SUB_BOARDS * SUB_BOARDS;
+
+/// Every way to get three in a row on a 3x3 board, for the sub-boards
+/// and for the big board made of them alike.
+pub(crate) const CLASSIC_LINES: [[usize; 3]; 8] = [
+    [0, 1, 2], [3, 4, 5], [6, 7, 8],
+    [0, 3, 6], [1, 4, 7], [2, 5, 8],
+    [0, 4, 8], [2, 4, 6]
+];
+
+/// Nine classic boards laid out as one big 3x3 board. Winning a
+/// sub-board claims that square of the big board, and three claimed
+/// squares in a row win the game. Whichever cell you mark decides the
+/// sub-board your opponent has to play in next, unless that one is
+/// already decided, in which case they may play anywhere.
+///
+/// Cells are numbered sub-board by sub-board, so `cell / 9` is the
+/// sub-board and `cell % 9` the cell within it, both row by row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UltimateBoard {
+    cells: [Cell; ULTIMATE_CELLS],
+    // Who took each sub-board. A full sub-board nobody won stays `None`.
+    claimed: [Option<Player>; SUB_BOARDS],
+    forced: Option<usize>,
+    winner: Option<(Player, Line)>
+}
+
+impl Default for UltimateBoard {
+    fn default() -> Self {
+        UltimateBoard::new()
+    }
+}
+
+impl UltimateBoard {
+    pub fn new() -> Self {
+        Self { cells: [Cell::Empty; ULTIMATE_CELLS], claimed: [None; SUB_BOARDS], forced: None, winner: None }
+    }
+
+    /// A board with the given cells where the next move has to go to
+    /// `forced`, or `None` if the cells don't fit or that sub-board
+    /// is already decided.
+    pub fn from_cells(cells: &[Cell], forced: Option<usize>) -> Option<Self> {
+        if cells.len() != ULTIMATE_CELLS || cells.iter().any(|cell| matches!(cell, Cell::Number(_))) {
+            return None;
+        }
+        let mut board = UltimateBoard::new();
+        board.cells.copy_from_slice(cells);
+        for sub_board in 0..SUB_BOARDS {
+            board.claimed[sub_board] = board.sub_board_line(sub_board);
+        }
+        board.winner = board.big_line();
+        if forced.is_some_and(|forced| forced >= SUB_BOARDS || board.is_decided(forced)) {
+            return None;
+        }
+        board.forced = forced;
+        Some(board)
+    }
+
+    /// Splits a cell number into its sub-board and the cell within it.
+    pub fn split(cell: usize) -> (usize, usize) {
+        (cell / SUB_BOARDS, cell % SUB_BOARDS)
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        self.cells.get(index).copied()
+    }
+
+    /// Who won the sub-board, if anybody did.
+    pub fn claimed_by(&self, sub_board: usize) -> Option<Player> {
+        self.claimed.get(sub_board).copied().flatten()
+    }
+
+    /// Won, or full without a winner. Either way nobody can play there anymore.
+    pub fn is_decided(&self, sub_board: usize) -> bool {
+        self.claimed_by(sub_board).is_some() || self.sub_cells(sub_board).iter().all(|cell| *cell != Cell::Empty)
+    }
+
+    /// The sub-board the next move has to go to, `None` when any open one will do.
+    pub fn forced_board(&self) -> Option<usize> {
+        self.forced
+    }
+
+    /// Whether the next move may go to `sub_board`.
+    pub fn is_playable(&self, sub_board: usize) -> bool {
+        sub_board < SUB_BOARDS && !self.is_decided(sub_board) && self.forced.is_none_or(|forced| forced == sub_board)
+    }
+
+    pub fn turn(&self) -> Player {
+        let xs = self.cells.iter().filter(|cell| **cell == Cell::Taken(Player::X)).count();
+        let os = self.cells.iter().filter(|cell| **cell == Cell::Taken(Player::O)).count();
+
+        if xs == os {
+            Player::X
+        } else {
+            Player::O
+        }
+    }
+
+    /// The three sub-boards that won the game and who claimed them.
+    pub fn winning_line(&self) -> Option<(Player, Line)> {
+        self.winner
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some((player, _)) = self.winner {
+            return Some(Outcome::Win(player));
+        }
+
+        if (0..SUB_BOARDS).all(|sub_board| self.is_decided(sub_board)) {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate_move(&self, mv: Move) -> Result<(), MoveError> {
+        if self.outcome().is_some() {
+            return Err(MoveError::GameOver);
+        }
+        let (sub_board, _) = Self::split(mv.cell);
+        match self.cell(mv.cell) {
+            None => Err(MoveError::OutOfBounds),
+            Some(Cell::Taken(_) | Cell::Number(_)) => Err(MoveError::CellOccupied),
+            Some(Cell::Empty) if !self.is_playable(sub_board) => Err(MoveError::WrongSubBoard),
+            Some(Cell::Empty) if mv.player != self.turn() => Err(MoveError::NotYourTurn),
+            Some(Cell::Empty) if mv.piece != Piece::Mark(mv.player) => Err(MoveError::WrongPiece),
+            Some(Cell::Empty) => Ok(())
+        }
+    }
+
+    /// Places the mark if the move is legal, claims the sub-board if
+    /// that completed a line in it, and returns the outcome if this
+    /// move ended the game.
+    pub fn apply_move(&mut self, mv: Move) -> Result<Option<Outcome>, MoveError> {
+        self.validate_move(mv)?;
+        self.cells[mv.cell] = Cell::Taken(mv.player);
+
+        let (sub_board, sent_to) = Self::split(mv.cell);
+        if self.claimed[sub_board].is_none() {
+            self.claimed[sub_board] = self.sub_board_line(sub_board);
+            if self.claimed[sub_board].is_some() {
+                self.winner = self.big_line();
+            }
+        }
+        self.forced = (!self.is_decided(sent_to)).then_some(sent_to);
+        Ok(self.outcome())
+    }
+
+    fn sub_cells(&self, sub_board: usize) -> &[Cell] {
+        &self.cells[sub_board * SUB_BOARDS..(sub_board + 1) * SUB_BOARDS]
+    }
+
+    /// Owner of a completed line within the sub-board.
+    fn sub_board_line(&self, sub_board: usize) -> Option<Player> {
+        let cells = self.sub_cells(sub_board);
+        CLASSIC_LINES.iter().find_map(|[a, b, c]| match cells[*a] {
+            Cell::Taken(player) if cells[*b] == cells[*a] && cells[*c] == cells[*a] => Some(player),
+            _ => None
+        })
+    }
+
+    /// A line of sub-boards claimed by the same player.
+    fn big_line(&self) -> Option<(Player, Line)> {
+        CLASSIC_LINES.iter().find_map(|line| match self.claimed[line[0]] {
+            Some(player) if line.iter().all(|sub_board| self.claimed[*sub_board] == Some(player)) => {
+                Some((player, Line::from_cells(line)))
+            },
+            _ => None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(board: &mut UltimateBoard, sub_board: usize, cell: usize) -> Result<Option<Outcome>, MoveError> {
+        let player = board.turn();
+        board.apply_move(Move::new(sub_board * SUB_BOARDS + cell, player))
+    }
+
+    #[test]
+    fn move_sends_opponent_to_sub_board() {
+        let mut board = UltimateBoard::new();
+        assert_eq!(board.forced_board(), None);
+        play(&mut board, 4, 2).unwrap();
+        assert_eq!(board.forced_board(), Some(2));
+        assert_eq!(play(&mut board, 3, 0), Err(MoveError::WrongSubBoard));
+        play(&mut board, 2, 4).unwrap();
+        assert_eq!(board.forced_board(), Some(4));
+    }
+
+    #[test]
+    fn decided_sub_board_frees_the_next_move() {
+        let mut board = UltimateBoard::new();
+        // O keeps sending X back to sub-board 0 until X has its top row.
+        for (sub_board, cell) in [(4, 4), (4, 0), (0, 1), (1, 0), (0, 2), (2, 0)] {
+            play(&mut board, sub_board, cell).unwrap();
+        }
+        assert_eq!(board.forced_board(), Some(0));
+        play(&mut board, 0, 0).unwrap();
+        assert_eq!(board.claimed_by(0), Some(Player::X));
+
+        // That sends O to sub-board 0 itself, which is decided, so O can go anywhere open.
+        assert_eq!(board.forced_board(), None);
+        assert!(!board.is_playable(0));
+        assert_eq!(play(&mut board, 0, 3), Err(MoveError::WrongSubBoard));
+        play(&mut board, 7, 7).unwrap();
+    }
+
+    #[test]
+    fn three_sub_boards_in_a_row_win() {
+        let mut board = UltimateBoard::new();
+        // X takes sub-boards 0, 1 and 2 with their top rows; O plays along in 3 to 8.
+        let moves = [
+            (0, 3), (3, 0), (0, 4), (4, 0), (0, 5), (5, 1),
+            (1, 3), (3, 1), (1, 4), (4, 1), (1, 5), (5, 2),
+            (2, 3), (3, 2), (2, 4), (4, 2)
+        ];
+        for (sub_board, cell) in moves {
+            assert_eq!(play(&mut board, sub_board, cell), Ok(None), "{sub_board} {cell}");
+        }
+        assert_eq!(board.claimed_by(0), Some(Player::X));
+        assert_eq!(board.claimed_by(1), Some(Player::X));
+        assert_eq!(board.claimed_by(3), Some(Player::O));
+        assert_eq!(play(&mut board, 2, 5), Ok(Some(Outcome::Win(Player::X))));
+        let (winner, line) = board.winning_line().unwrap();
+        assert_eq!(winner, Player::X);
+        assert_eq!(line.cells(), &[0, 1, 2]);
+
+        let rebuilt = UltimateBoard::from_cells(board.cells(), None).unwrap();
+        assert_eq!(rebuilt.outcome(), Some(Outcome::Win(Player::X)));
+        assert!(UltimateBoard::from_cells(board.cells(), Some(0)).is_none());
+    }
+}
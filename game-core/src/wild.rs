@@ -0,0 +1,126 @@
+use crate::board::{Cell, Line, Move, MoveError, Outcome, Piece};
+use crate::ultimate::CLASSIC_LINES;
+use crate::Player;
+
+/// Classic board where both players may put down either mark. Whoever
+/// completes three of a kind in a row wins, whichever mark it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct WildBoard {
+    cells: [Cell; 9],
+    // Paired with whoever completed the line, not whose mark fills it.
+    winner: Option<(Player, Line)>
+}
+
+impl WildBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A board with the given cells, or `None` if there aren't nine
+    /// or some hold numbers.
+    pub fn from_cells(cells: &[Cell]) -> Option<Self> {
+        if cells.len() != 9 || cells.iter().any(|cell| matches!(cell, Cell::Number(_))) {
+            return None;
+        }
+        let mut board = WildBoard::new();
+        board.cells.copy_from_slice(cells);
+        // A finished game ends on the winning move, so the last mover won.
+        let last_mover = board.turn().opposite();
+        board.winner = CLASSIC_LINES.iter()
+            .find(|line| board.is_complete(line))
+            .map(|line| (last_mover, Line::from_cells(line)));
+        Some(board)
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn cell(&self, index: usize) -> Option<Cell> {
+        self.cells.get(index).copied()
+    }
+
+    /// Marks don't tell who put them down, so turns just alternate.
+    pub fn turn(&self) -> Player {
+        let filled = self.cells.iter().filter(|cell| **cell != Cell::Empty).count();
+        if filled % 2 == 0 {
+            Player::X
+        } else {
+            Player::O
+        }
+    }
+
+    /// The completed line and the player who completed it.
+    pub fn winning_line(&self) -> Option<(Player, Line)> {
+        self.winner
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some((player, _)) = self.winner {
+            return Some(Outcome::Win(player));
+        }
+
+        if self.cells.iter().all(|cell| *cell != Cell::Empty) {
+            Some(Outcome::Draw)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate_move(&self, mv: Move) -> Result<(), MoveError> {
+        if self.outcome().is_some() {
+            return Err(MoveError::GameOver);
+        }
+        match self.cell(mv.cell) {
+            None => Err(MoveError::OutOfBounds),
+            Some(Cell::Taken(_) | Cell::Number(_)) => Err(MoveError::CellOccupied),
+            Some(Cell::Empty) if mv.player != self.turn() => Err(MoveError::NotYourTurn),
+            Some(Cell::Empty) if matches!(mv.piece, Piece::Number(_)) => Err(MoveError::WrongPiece),
+            Some(Cell::Empty) => Ok(())
+        }
+    }
+
+    /// Puts down the chosen mark if the move is legal and returns the
+    /// outcome if this move ended the game.
+    pub fn apply_move(&mut self, mv: Move) -> Result<Option<Outcome>, MoveError> {
+        self.validate_move(mv)?;
+        self.cells[mv.cell] = mv.piece.into();
+        self.winner = CLASSIC_LINES.iter()
+            .filter(|line| line.contains(&mv.cell))
+            .find(|line| self.is_complete(line))
+            .map(|line| (mv.player, Line::from_cells(line)));
+        Ok(self.outcome())
+    }
+
+    fn is_complete(&self, [a, b, c]: &[usize; 3]) -> bool {
+        matches!(self.cells[*a], Cell::Taken(_)) && self.cells[*b] == self.cells[*a] && self.cells[*c] == self.cells[*a]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completing_either_mark_wins() {
+        let mut board = WildBoard::new();
+        // X puts down O marks on the top row, and O is made to complete it.
+        let moves = [(0, Player::O), (4, Player::X), (1, Player::O), (8, Player::X)];
+        for (cell, mark) in moves {
+            let player = board.turn();
+            assert_eq!(board.apply_move(Move { cell, player, piece: Piece::Mark(mark) }), Ok(None));
+        }
+        assert_eq!(board.turn(), Player::X);
+        let finish = Move { cell: 2, player: Player::X, piece: Piece::Mark(Player::O) };
+        assert_eq!(board.apply_move(finish), Ok(Some(Outcome::Win(Player::X))));
+        assert_eq!(board.winning_line().unwrap().1.cells(), &[0, 1, 2]);
+        assert_eq!(WildBoard::from_cells(board.cells()).unwrap().outcome(), Some(Outcome::Win(Player::X)));
+    }
+
+    #[test]
+    fn numbers_are_not_marks() {
+        let board = WildBoard::new();
+        let number = Move { cell: 0, player: Player::X, piece: Piece::Number(1) };
+        assert_eq!(board.validate_move(number), Err(MoveError::WrongPiece));
+    }
+}
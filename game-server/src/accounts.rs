@@ -0,0 +1,332 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::db::{Database, DatabaseError};
+use crate::rating::INITIAL_RATING;
+use crate::registry::PlayerId;
+
+const PROFILE_COLUMNS: &str = "players.id, players.display_name, players.rating, players.rated_games";
+const TOKEN_BYTES: usize = 32;
+const MAX_NAME_CHARS: usize = 20;
+
+/// Who a player is across visits. Everyone starts out anonymous and
+/// may pick a display name later.
+#[derive(Clone, Debug, Serialize)]
+pub struct Profile {
+    pub id: PlayerId,
+    pub display_name: Option<String>,
+    pub rating: i32,
+    // Games against other players; bot games aren't rated.
+    pub rated_games: u32
+}
+
+impl Profile {
+    /// What opponents and spectators see.
+    pub fn label(&self) -> String {
+        label(self.id, self.display_name.as_deref())
+    }
+}
+
+/// The display name, or a stand-in for players who haven't picked one.
+pub fn label(id: PlayerId, display_name: Option<&str>) -> String {
+    match display_name {
+        Some(name) => name.to_string(),
+        None => format!("Player {id}")
+    }
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    Database(DatabaseError),
+    InvalidName
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Database(err) => write!(f, "{err}"),
+            AccountError::InvalidName => write!(f, "display names are 1 to {MAX_NAME_CHARS} printable characters")
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl From<DatabaseError> for AccountError {
+    fn from(err: DatabaseError) -> Self {
+        AccountError::Database(err)
+    }
+}
+
+/// Profiles and login sessions. Clients only ever hold an opaque random
+/// token; the database keeps its hash, so a leaked database doesn't let
+/// anyone log in.
+pub struct AccountStore {
+    db: Database,
+    session_lifetime: Duration
+}
+
+impl AccountStore {
+    pub fn new(db: Database, session_lifetime: Duration) -> Self {
+        AccountStore { db, session_lifetime }
+    }
+
+    /// How long a token stays valid since it was last used.
+    pub fn session_lifetime(&self) -> Duration {
+        self.session_lifetime
+    }
+
+    /// Creates an anonymous player and logs them in. Returns the token
+    /// to hand to the client.
+    pub async fn sign_up(&self) -> Result<(Profile, String), AccountError> {
+        let token = new_token();
+        let token_hash = hash_token(&token);
+        let profile = self.db.run(move |db| {
+            let tx = db.transaction()?;
+            let now = unix_now();
+            tx.execute("INSERT INTO players (created_at) VALUES (?1)", params![now])?;
+            let id = tx.last_insert_rowid();
+            tx.execute("INSERT INTO sessions (token_hash, player_id, last_seen) VALUES (?1, ?2, ?3)", params![token_hash, id, now])?;
+            tx.commit()?;
+            Ok(Profile { id: id as PlayerId, display_name: None, rating: INITIAL_RATING, rated_games: 0 })
+        }).await?;
+        info!("new player {}", profile.id);
+        Ok((profile, token))
+    }
+
+    /// The player a token belongs to, or `None` if it's unknown or
+    /// expired. Using a token keeps it alive.
+    pub async fn authenticate(&self, token: &str) -> Result<Option<Profile>, AccountError> {
+        let token_hash = hash_token(token);
+        let oldest = self.oldest_session();
+        Ok(self.db.run(move |db| {
+            let now = unix_now();
+            let updated = db.execute(
+                "UPDATE sessions SET last_seen = ?1 WHERE token_hash = ?2 AND last_seen >= ?3",
+                params![now, token_hash, oldest]
+            )?;
+            if updated == 0 {
+                return Ok(None);
+            }
+            let sql = format!(
+                "SELECT {PROFILE_COLUMNS} FROM sessions JOIN players ON players.id = sessions.player_id WHERE sessions.token_hash = ?1"
+            );
+            db.query_row(&sql, params![token_hash], profile).optional()
+        }).await?)
+    }
+
+    /// Sets or, with `None`, clears the player's display name.
+    pub async fn rename(&self, player_id: PlayerId, display_name: Option<String>) -> Result<Profile, AccountError> {
+        let display_name = match display_name {
+            Some(name) => Some(valid_name(&name).ok_or(AccountError::InvalidName)?),
+            None => None
+        };
+        Ok(self.db.run(move |db| {
+            db.execute("UPDATE players SET display_name = ?1 WHERE id = ?2", params![display_name, player_id as i64])?;
+            let sql = format!("SELECT {PROFILE_COLUMNS} FROM players WHERE id = ?1");
+            db.query_row(&sql, params![player_id as i64], profile)
+        }).await?)
+    }
+
+    /// Deletes expired logins, then players who have no login left and
+    /// no finished games to show for it, since nobody can be them again.
+    pub async fn clean_up(&self) -> Result<(), AccountError> {
+        let oldest = self.oldest_session();
+        let (sessions, players) = self.db.run(move |db| {
+            let tx = db.transaction()?;
+            let sessions = tx.execute("DELETE FROM sessions WHERE last_seen < ?1", params![oldest])?;
+            let players = tx.execute(
+                "DELETE FROM players WHERE
+                    NOT EXISTS (SELECT 1 FROM sessions WHERE sessions.player_id = players.id)
+                    AND NOT EXISTS (SELECT 1 FROM games WHERE games.x_player = players.id OR games.o_player = players.id)",
+                []
+            )?;
+            tx.commit()?;
+            Ok((sessions, players))
+        }).await?;
+        if sessions > 0 || players > 0 {
+            info!("deleted {sessions} expired sessions and {players} players without games");
+        }
+        Ok(())
+    }
+
+    /// Anyone's profile, or `None` if there is no such player.
+    pub async fn profile(&self, player_id: PlayerId) -> Result<Option<Profile>, AccountError> {
+        Ok(self.db.run(move |db| {
+            let sql = format!("SELECT {PROFILE_COLUMNS} FROM players WHERE id = ?1");
+            db.query_row(&sql, params![player_id as i64], profile).optional()
+        }).await?)
+    }
+
+    /// Logins last used before this time have expired.
+    fn oldest_session(&self) -> i64 {
+        let lifetime = i64::try_from(self.session_lifetime.as_secs()).unwrap_or(i64::MAX);
+        unix_now().saturating_sub(lifetime)
+    }
+}
+
+fn profile(row: &Row) -> rusqlite::Result<Profile> {
+    Ok(Profile {
+        id: row.get::<_, i64>(0)? as PlayerId,
+        display_name: row.get(1)?,
+        rating: row.get(2)?,
+        rated_games: row.get(3)?
+    })
+}
+
+/// Trimmed name, or `None` if it's empty, too long or has control characters.
+fn valid_name(name: &str) -> Option<String> {
+    let name = name.trim();
+    let length = name.chars().count();
+    ((1..=MAX_NAME_CHARS).contains(&length) && !name.chars().any(char::is_control)).then(|| name.to_string())
+}
+
+fn new_token() -> String {
+    let bytes: [u8; TOKEN_BYTES] = rand::random();
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    const LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn store() -> AccountStore {
+        AccountStore::new(Database::open(Path::new(":memory:")).unwrap(), LIFETIME)
+    }
+
+    // Moves the player's last login `secs` further into the past.
+    async fn age(store: &AccountStore, player_id: PlayerId, secs: i64) {
+        store.db.run(move |db| {
+            db.execute("UPDATE sessions SET last_seen = last_seen - ?1 WHERE player_id = ?2", params![secs, player_id as i64])
+        }).await.unwrap();
+    }
+
+    async fn last_seen(store: &AccountStore, player_id: PlayerId) -> i64 {
+        store.db.run(move |db| {
+            db.query_row("SELECT last_seen FROM sessions WHERE player_id = ?1", params![player_id as i64], |row| row.get(0))
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn token_logs_in_as_its_player() {
+        let store = store();
+        let (player, token) = store.sign_up().await.unwrap();
+        let (other, _) = store.sign_up().await.unwrap();
+        assert_ne!(player.id, other.id);
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+
+        let profile = store.authenticate(&token).await.unwrap().unwrap();
+        assert_eq!(profile.id, player.id);
+        assert_eq!(profile.rating, INITIAL_RATING);
+        assert_eq!(profile.label(), format!("Player {}", player.id));
+    }
+
+    #[tokio::test]
+    async fn only_the_hash_is_stored() {
+        let store = store();
+        let (_, token) = store.sign_up().await.unwrap();
+        let stored: Vec<u8> = store.db.run(|db| db.query_row("SELECT token_hash FROM sessions", [], |row| row.get(0))).await.unwrap();
+        assert_eq!(stored, hash_token(&token));
+        assert_ne!(stored, token.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn unknown_token_is_nobody() {
+        let store = store();
+        store.sign_up().await.unwrap();
+        assert!(store.authenticate("bogus").await.unwrap().is_none());
+        assert!(store.authenticate(&new_token()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_nobody() {
+        let store = store();
+        let (player, token) = store.sign_up().await.unwrap();
+        age(&store, player.id, LIFETIME.as_secs() as i64 + 1).await;
+        assert!(store.authenticate(&token).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn endless_lifetime_never_expires() {
+        let store = AccountStore::new(Database::open(Path::new(":memory:")).unwrap(), Duration::from_secs(u64::MAX));
+        let (player, token) = store.sign_up().await.unwrap();
+        store.clean_up().await.unwrap();
+        assert_eq!(store.authenticate(&token).await.unwrap().map(|profile| profile.id), Some(player.id));
+    }
+
+    #[tokio::test]
+    async fn using_a_token_keeps_it_alive() {
+        let store = store();
+        let (player, token) = store.sign_up().await.unwrap();
+        age(&store, player.id, LIFETIME.as_secs() as i64 - 10).await;
+        assert!(store.authenticate(&token).await.unwrap().is_some());
+        assert!(last_seen(&store, player.id).await >= unix_now() - 1);
+
+        // A whole lifetime since it was first issued, but not since last used.
+        age(&store, player.id, 20).await;
+        assert!(store.authenticate(&token).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn clean_up_keeps_players_with_games() {
+        let store = store();
+        let (active, _) = store.sign_up().await.unwrap();
+        let (gone, _) = store.sign_up().await.unwrap();
+        let (played, _) = store.sign_up().await.unwrap();
+        age(&store, gone.id, LIFETIME.as_secs() as i64 + 1).await;
+        age(&store, played.id, LIFETIME.as_secs() as i64 + 1).await;
+        let played_id = played.id as i64;
+        store.db.run(move |db| db.execute(
+            "INSERT INTO games (variant, x_player, x_label, o_label, outcome, started_at, finished_at)
+            VALUES ('standard-3-3', ?1, 'X', 'Bot', 'draw', 0, 0)",
+            params![played_id]
+        )).await.unwrap();
+
+        store.clean_up().await.unwrap();
+        assert!(store.profile(active.id).await.unwrap().is_some());
+        assert!(store.profile(gone.id).await.unwrap().is_none());
+        assert!(store.profile(played.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn rename_checks_the_name() {
+        let store = store();
+        let (player, _) = store.sign_up().await.unwrap();
+        let renamed = store.rename(player.id, Some("  Ada  ".to_string())).await.unwrap();
+        assert_eq!(renamed.display_name.as_deref(), Some("Ada"));
+        assert!(matches!(store.rename(player.id, Some(" ".to_string())).await, Err(AccountError::InvalidName)));
+        let anonymous = store.rename(player.id, None).await.unwrap();
+        assert_eq!(anonymous.display_name, None);
+    }
+
+    #[test]
+    fn names_are_short_and_printable() {
+        assert_eq!(valid_name("Ada"), Some("Ada".to_string()));
+        assert_eq!(valid_name("  Ada Lovelace \t"), Some("Ada Lovelace".to_string()));
+        assert_eq!(valid_name(&"x".repeat(MAX_NAME_CHARS)), Some("x".repeat(MAX_NAME_CHARS)));
+        // Counted in characters, not bytes.
+        assert_eq!(valid_name(&"é".repeat(MAX_NAME_CHARS)), Some("é".repeat(MAX_NAME_CHARS)));
+        assert_eq!(valid_name(&"x".repeat(MAX_NAME_CHARS + 1)), None);
+        assert_eq!(valid_name(""), None);
+        assert_eq!(valid_name("   "), None);
+        assert_eq!(valid_name("bell\u{7}"), None);
+        assert_eq!(valid_name("two\nlines"), None);
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::extract::{FromRequestParts, OptionalFromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use axum_extra::headers::Cookie;
+use axum_extra::TypedHeader;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::accounts::{AccountError, Profile};
+use crate::db::DatabaseError;
+use crate::history::{GameDetails, GameSummary, Page};
+use crate::leaderboard::{LeaderboardFilter, Standing};
+use crate::registry::PlayerId;
+use crate::AppState;
+
+const SESSION_COOKIE: &str = "SESSION";
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+/// JSON endpoints under `/api`. The profile is the logged in player's
+/// own, and naming it signs up visitors who aren't players yet; other
+/// players' profiles, finished games and the leaderboard are open to
+/// anyone.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/profile", get(get_profile).put(put_profile))
+        .route("/games", get(list_games))
+        .route("/games/{id}", get(get_game))
+        .route("/games/{id}/replay", get(get_replay))
+        .route("/players/{id}", get(get_player))
+        .route("/players/{id}/games", get(list_player_games))
+        .route("/leaderboard", get(get_leaderboard))
+}
+
+/// The player behind the request's session cookie. Requests without a
+/// valid one are turned away with 401.
+pub struct LoggedIn(pub Profile);
+
+impl FromRequestParts<Arc<AppState>> for LoggedIn {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        <LoggedIn as OptionalFromRequestParts<_>>::from_request_parts(parts, state).await?.ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// `None` for visitors without a valid session, who may still look around.
+impl OptionalFromRequestParts<Arc<AppState>> for LoggedIn {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Option<Self>, Self::Rejection> {
+        let cookie = <TypedHeader<Cookie> as FromRequestParts<_>>::from_request_parts(parts, state).await.ok();
+        let Some(token) = cookie.as_ref().and_then(|TypedHeader(cookie)| cookie.get(SESSION_COOKIE)) else {
+            return Ok(None);
+        };
+        match state.accounts.authenticate(token).await {
+            Ok(profile) => Ok(profile.map(LoggedIn)),
+            Err(err) => {
+                error!("cannot check a session: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// The logged in player, or else a new anonymous one. Only actions that
+/// need a player sign visitors up, so page views don't fill the database.
+pub struct SignedIn {
+    pub profile: Profile,
+    // The new player's session, for the response to hand to the client.
+    cookie: Option<HeaderValue>
+}
+
+impl SignedIn {
+    /// Adds the session cookie to `response` if the player is new.
+    pub fn with_cookie(self, response: impl IntoResponse) -> Response {
+        let mut response = response.into_response();
+        if let Some(cookie) = self.cookie {
+            response.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
+        response
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for SignedIn {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if let Some(LoggedIn(profile)) = <LoggedIn as OptionalFromRequestParts<_>>::from_request_parts(parts, state).await? {
+            return Ok(SignedIn { profile, cookie: None });
+        }
+        match state.accounts.sign_up().await {
+            Ok((profile, token)) => Ok(SignedIn { profile, cookie: Some(session_cookie(state, &token)) }),
+            Err(err) => {
+                error!("cannot sign up a new player: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Scripts can't read the token, and other sites can't send it along.
+fn session_cookie(state: &AppState, token: &str) -> HeaderValue {
+    let max_age = state.accounts.session_lifetime().as_secs();
+    let secure = if state.secure_cookie { "; Secure" } else { "" };
+    let cookie = format!("{SESSION_COOKIE}={token}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}");
+    HeaderValue::from_str(&cookie).expect("tokens are hex")
+}
+
+#[derive(Deserialize)]
+struct ProfileUpdate {
+    // Absent or null to go back to being anonymous.
+    display_name: Option<String>
+}
+
+async fn get_profile(LoggedIn(profile): LoggedIn) -> Json<Profile> {
+    Json(profile)
+}
+
+async fn put_profile(
+    signed_in: SignedIn,
+    State(state): State<Arc<AppState>>,
+    Json(update): Json<ProfileUpdate>
+) -> Response {
+    let profile = signed_in.profile.clone();
+    match state.accounts.rename(profile.id, update.display_name).await {
+        Ok(profile) => signed_in.with_cookie(Json(profile)),
+        Err(err @ AccountError::InvalidName) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Err(err) => {
+            error!("cannot rename player {}: {err}", profile.id);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_player(
+    Path(player_id): Path<PlayerId>,
+    State(state): State<Arc<AppState>>
+) -> Result<Json<Profile>, StatusCode> {
+    match state.accounts.profile(player_id).await {
+        Ok(Some(profile)) => Ok(Json(profile)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("cannot look up player {player_id}: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// `?limit=&before=` on game lists: newest first, and `before` takes
+/// the last id of the previous page.
+#[derive(Deserialize)]
+struct PageQuery {
+    limit: Option<u32>,
+    before: Option<i64>
+}
+
+impl From<PageQuery> for Page {
+    fn from(query: PageQuery) -> Self {
+        Page {
+            limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+            before: query.before
+        }
+    }
+}
+
+async fn list_games(
+    Query(page): Query<PageQuery>,
+    State(state): State<Arc<AppState>>
+) -> Result<Json<Vec<GameSummary>>, StatusCode> {
+    state.history.games(page.into()).await.map(Json).map_err(internal_error("list finished games"))
+}
+
+async fn get_game(
+    Path(id): Path<i64>,
+    State(state): State<Arc<AppState>>
+) -> Result<Json<GameDetails>, StatusCode> {
+    match state.history.game(id).await {
+        Ok(Some(game)) => Ok(Json(game)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => Err(internal_error("read a finished game")(err))
+    }
+}
+
+/// The game as a protobuf `Replay` message, offered as a file download.
+async fn get_replay(
+    Path(id): Path<i64>,
+    State(state): State<Arc<AppState>>
+) -> Result<Response, StatusCode> {
+    match state.history.replay(id).await {
+        Ok(Some(replay)) => Ok((
+            [
+                (header::CONTENT_TYPE, "application/x-protobuf".to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"game-{id}.replay\""))
+            ],
+            replay
+        ).into_response()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => Err(internal_error("read a replay")(err))
+    }
+}
+
+async fn list_player_games(
+    Path(player_id): Path<PlayerId>,
+    Query(page): Query<PageQuery>,
+    State(state): State<Arc<AppState>>
+) -> Result<Json<Vec<GameSummary>>, StatusCode> {
+    state.history.player_games(player_id, page.into()).await.map(Json).map_err(internal_error("list a player's games"))
+}
+
+/// `?variant=&days=&limit=&offset=`: only games in that variant, named
+/// like `standard-3-3`, finished in the last `days`, and a page of the
+/// ranking. An unknown variant is a bad request.
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    variant: Option<String>,
+    days: Option<u64>,
+    limit: Option<u32>,
+    offset: Option<u32>
+}
+
+impl LeaderboardQuery {
+    /// `None` if the variant isn't one we know, see [`messages::variant_slug`].
+    fn filter(&self) -> Option<LeaderboardFilter> {
+        let variant = match &self.variant {
+            Some(slug) => Some(messages::parse_variant_slug(slug)?),
+            None => None
+        };
+        Some(LeaderboardFilter {
+            variant,
+            since: self.days.and_then(|days| SystemTime::now().checked_sub(Duration::from_secs(days.saturating_mul(24 * 60 * 60))))
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct LeaderboardPage {
+    players: Vec<Standing>,
+    // The logged in player's own standing, wherever it is.
+    you: Option<Standing>
+}
+
+async fn get_leaderboard(
+    logged_in: Option<LoggedIn>,
+    Query(query): Query<LeaderboardQuery>,
+    State(state): State<Arc<AppState>>
+) -> Result<Json<LeaderboardPage>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let filter = || query.filter().ok_or(StatusCode::BAD_REQUEST);
+    let players = state.leaderboard.top(filter()?, limit, query.offset.unwrap_or_default()).await.map_err(internal_error("rank the leaderboard"))?;
+    let you = match logged_in {
+        Some(LoggedIn(profile)) => state.leaderboard.standing(profile.id, filter()?).await.map_err(internal_error("find a player on the leaderboard"))?,
+        None => None
+    };
+    Ok(Json(LeaderboardPage { players, you }))
+}
+
+/// Logs what the handler couldn't do, and answers with 500.
+fn internal_error(what: &'static str) -> impl FnOnce(DatabaseError) -> StatusCode {
+    move |err| {
+        error!("cannot {what}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(limit: Option<u32>) -> Page {
+        PageQuery { limit, before: Some(7) }.into()
+    }
+
+    #[test]
+    fn page_size_defaults_and_is_capped() {
+        assert_eq!(page(None).limit, DEFAULT_PAGE_SIZE);
+        assert_eq!(page(Some(10)).limit, 10);
+        assert_eq!(page(Some(0)).limit, 1);
+        assert_eq!(page(Some(MAX_PAGE_SIZE)).limit, MAX_PAGE_SIZE);
+        assert_eq!(page(Some(MAX_PAGE_SIZE + 1)).limit, MAX_PAGE_SIZE);
+        assert_eq!(page(None).before, Some(7));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use game_core::ai::{self, Difficulty};
+use game_core::{Game, Player};
+use messages::game::PlayerType;
+use messages::game::server_message::Message as Com_Message;
+use messages::game::{RematchRequest, ServerMessage};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+use crate::session::{Connection, SessionCommand, SessionHandle};
+
+// Bots never disconnect, and real connections are numbered from 1.
+const BOT_CONNECTION_ID: u64 = 0;
+
+// Replying instantly feels wrong, so the bot pretends to think a little.
+const THINKING_TIME: Duration = Duration::from_millis(600);
+
+/// A computer player. It sits in a session through an ordinary
+/// [`Connection`], so the session can't tell it from a human.
+pub struct Bot {
+    difficulty: Difficulty,
+    events: mpsc::Receiver<ServerMessage>
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty) -> (Self, Connection) {
+        let (outbound, events) = mpsc::channel(16);
+        (Bot { difficulty, events }, Connection::new(BOT_CONNECTION_ID, outbound))
+    }
+
+    pub fn label(difficulty: Difficulty) -> String {
+        format!("Bot ({})", format!("{difficulty:?}").to_lowercase())
+    }
+
+    /// Plays in `session` until it ends, agreeing to every rematch. Like
+    /// any client, it learns its side and the rules from `InitGame`.
+    pub fn spawn(self, session: SessionHandle) {
+        tokio::spawn(self.run(session));
+    }
+
+    async fn run(mut self, session: SessionHandle) {
+        let mut board = Game::default();
+        let mut player = Player::X;
+        // The side of the first game, which the session knows us by.
+        let mut seat = None;
+
+        while let Some(ServerMessage { message: Some(message), .. }) = self.events.recv().await {
+            match message {
+                Com_Message::InitGame(init) => {
+                    let Some(variant) = messages::decode_variant(init.rules.as_ref()) else {
+                        warn!("bot got rules it can't play: {:?}", init.rules);
+                        break;
+                    };
+                    board = Game::new(variant);
+                    player = PlayerType::try_from(init.your_player).unwrap_or(PlayerType::X).into();
+                    let seat = *seat.get_or_insert(player);
+                    if player == Player::X {
+                        self.play(&mut board, seat, &session).await;
+                    }
+                },
+                Com_Message::PlayerMove(opponent_move) => {
+                    let Some(mv) = messages::decode_move(&opponent_move, player.opposite()) else {
+                        warn!("bot got a malformed move: {opponent_move:?}");
+                        break;
+                    };
+                    if let Err(err) = board.apply_move(mv) {
+                        warn!("bot could not follow the game: {err:?}");
+                        break;
+                    }
+                    self.play(&mut board, seat.unwrap_or(player), &session).await;
+                },
+                Com_Message::RematchRequest(RematchRequest {}) => {
+                    session.send(SessionCommand::Rematch { player: seat.unwrap_or(player) }).await;
+                },
+                _ => {}
+            }
+        }
+        debug!("bot playing {player:?} is done");
+    }
+
+    async fn play(&self, board: &mut Game, seat: Player, session: &SessionHandle) {
+        // Searching bigger games takes a while, so keep it off the async workers.
+        let (game, difficulty) = (*board, self.difficulty);
+        let thinking = tokio::task::spawn_blocking(move || {
+            ai::choose_move(&game, difficulty, &mut |n| rand::random_range(0..n))
+        });
+        let (choice, _) = tokio::join!(thinking, sleep(THINKING_TIME));
+        let Ok(Some(mv)) = choice else {
+            return;
+        };
+        if board.apply_move(mv).is_ok() {
+            let player_move = messages::encode_move(board.variant(), mv);
+            let seq = board.moves_played() as u32;
+            session.send(SessionCommand::Move { player: seat, player_move, seq }).await;
+        }
+    }
+}
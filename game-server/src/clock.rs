@@ -0,0 +1,209 @@
+use std::fmt;
+use std::time::Duration;
+
+use game_core::Player;
+use messages::game::{self, time_control::Limit, ClockBank, ClockUpdate, PlayerType};
+use tokio::time::Instant;
+
+// Bounds on what clients may ask for. The bot spends `bot::THINKING_TIME`
+// on every move, so a shorter limit would lose it every game.
+const MIN_LIMIT: Duration = Duration::from_secs(1);
+const MAX_LIMIT: Duration = Duration::from_secs(60 * 60);
+const MAX_INCREMENT: Duration = Duration::from_secs(60);
+
+/// How long each side may think, picked per match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeControl {
+    /// Every move has to come within this long.
+    PerMove(Duration),
+    /// A bank of time per side for the whole game, topped up by
+    /// `increment` after each of their moves.
+    Bank { initial: Duration, increment: Duration }
+}
+
+impl TimeControl {
+    /// `None` when no limit is set or it is outside what we allow.
+    pub fn decode(time_control: &game::TimeControl) -> Option<Self> {
+        let decoded = match time_control.limit? {
+            Limit::PerMoveSecs(secs) => TimeControl::PerMove(Duration::from_secs(secs.into())),
+            Limit::Bank(bank) => TimeControl::Bank {
+                initial: Duration::from_secs(bank.initial_secs.into()),
+                increment: Duration::from_secs(bank.increment_secs.into())
+            }
+        };
+        let (limit, increment) = match decoded {
+            TimeControl::PerMove(limit) => (limit, Duration::ZERO),
+            TimeControl::Bank { initial, increment } => (initial, increment)
+        };
+        ((MIN_LIMIT..=MAX_LIMIT).contains(&limit) && increment <= MAX_INCREMENT).then_some(decoded)
+    }
+
+    fn limit(&self) -> Duration {
+        match self {
+            TimeControl::PerMove(limit) => *limit,
+            TimeControl::Bank { initial, .. } => *initial
+        }
+    }
+}
+
+impl From<TimeControl> for game::TimeControl {
+    fn from(time_control: TimeControl) -> Self {
+        let limit = match time_control {
+            TimeControl::PerMove(limit) => Limit::PerMoveSecs(limit.as_secs() as u32),
+            TimeControl::Bank { initial, increment } => Limit::Bank(ClockBank {
+                initial_secs: initial.as_secs() as u32,
+                increment_secs: increment.as_secs() as u32
+            })
+        };
+        game::TimeControl { limit: Some(limit) }
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeControl::PerMove(limit) => write!(f, "{}s per move", limit.as_secs()),
+            TimeControl::Bank { initial, increment } => write!(f, "{}s + {}s", initial.as_secs(), increment.as_secs())
+        }
+    }
+}
+
+/// Both sides' time in one match. Only the side to move is running,
+/// from when their turn started.
+pub struct Clock {
+    control: TimeControl,
+    x_remaining: Duration,
+    o_remaining: Duration,
+    running: Player,
+    turn_started: Instant
+}
+
+impl Clock {
+    /// Starts X's clock.
+    pub fn start(control: TimeControl, now: Instant) -> Self {
+        Clock {
+            control,
+            x_remaining: control.limit(),
+            o_remaining: control.limit(),
+            running: Player::X,
+            turn_started: now
+        }
+    }
+
+    /// The side whose clock is running, who loses if it runs out.
+    pub fn running(&self) -> Player {
+        self.running
+    }
+
+    /// When the running side's time is up.
+    pub fn deadline(&self) -> Instant {
+        self.turn_started + self.banked(self.running)
+    }
+
+    /// Stops the clock of the side that just moved and starts the other's.
+    pub fn switch(&mut self, now: Instant) {
+        let used = now.saturating_duration_since(self.turn_started);
+        let remaining = match self.control {
+            TimeControl::PerMove(limit) => limit,
+            TimeControl::Bank { increment, .. } => self.banked(self.running).saturating_sub(used) + increment
+        };
+        match self.running {
+            Player::X => self.x_remaining = remaining,
+            Player::O => self.o_remaining = remaining
+        }
+        self.running = self.running.opposite();
+        self.turn_started = now;
+    }
+
+    pub fn update(&self, now: Instant) -> ClockUpdate {
+        let remaining = |player: Player| {
+            let used = if player == self.running { now.saturating_duration_since(self.turn_started) } else { Duration::ZERO };
+            self.banked(player).saturating_sub(used).as_millis() as u32
+        };
+        ClockUpdate {
+            x_remaining_ms: remaining(Player::X),
+            o_remaining_ms: remaining(Player::O),
+            running: PlayerType::from(self.running) as i32
+        }
+    }
+
+    // Left at the start of their current or next turn.
+    fn banked(&self, player: Player) -> Duration {
+        match player {
+            Player::X => self.x_remaining,
+            Player::O => self.o_remaining
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn bank(initial_secs: u32, increment_secs: u32) -> game::TimeControl {
+        game::TimeControl { limit: Some(Limit::Bank(ClockBank { initial_secs, increment_secs })) }
+    }
+
+    #[test]
+    fn per_move_starts_every_turn_afresh() {
+        let start = Instant::now();
+        let mut clock = Clock::start(TimeControl::PerMove(secs(10)), start);
+        assert_eq!(clock.running(), Player::X);
+        assert_eq!(clock.deadline(), start + secs(10));
+
+        // However long X took, O gets the full limit.
+        clock.switch(start + secs(7));
+        assert_eq!(clock.running(), Player::O);
+        assert_eq!(clock.deadline(), start + secs(17));
+        clock.switch(start + secs(8));
+        assert_eq!(clock.deadline(), start + secs(18));
+    }
+
+    #[test]
+    fn bank_keeps_what_is_left_plus_increment() {
+        let start = Instant::now();
+        let mut clock = Clock::start(TimeControl::Bank { initial: secs(60), increment: secs(5) }, start);
+        assert_eq!(clock.deadline(), start + secs(60));
+
+        // X used 20 of 60, and gets 5 back for moving.
+        clock.switch(start + secs(20));
+        assert_eq!(clock.deadline(), start + secs(80));
+        // O used 30 of 60.
+        clock.switch(start + secs(50));
+        assert_eq!(clock.deadline(), start + secs(50) + secs(45));
+
+        let update = clock.update(start + secs(60));
+        assert_eq!(update.x_remaining_ms, 35_000);
+        assert_eq!(update.o_remaining_ms, 35_000);
+        assert_eq!(update.running, PlayerType::X as i32);
+    }
+
+    #[test]
+    fn bank_never_goes_below_the_increment() {
+        let start = Instant::now();
+        let mut clock = Clock::start(TimeControl::Bank { initial: secs(10), increment: secs(2) }, start);
+        // X overran, which the session catches on its own; the bank stays at zero.
+        clock.switch(start + secs(15));
+        clock.switch(start + secs(15));
+        assert_eq!(clock.deadline(), start + secs(15) + secs(2));
+    }
+
+    #[test]
+    fn decode_keeps_limits_within_bounds() {
+        let per_move = |secs| game::TimeControl { limit: Some(Limit::PerMoveSecs(secs)) };
+        assert_eq!(TimeControl::decode(&per_move(1)), Some(TimeControl::PerMove(MIN_LIMIT)));
+        assert_eq!(TimeControl::decode(&per_move(3600)), Some(TimeControl::PerMove(MAX_LIMIT)));
+        assert_eq!(TimeControl::decode(&per_move(0)), None);
+        assert_eq!(TimeControl::decode(&per_move(3601)), None);
+
+        assert_eq!(TimeControl::decode(&bank(300, 60)), Some(TimeControl::Bank { initial: secs(300), increment: MAX_INCREMENT }));
+        assert_eq!(TimeControl::decode(&bank(300, 61)), None);
+        assert_eq!(TimeControl::decode(&bank(0, 5)), None);
+        assert_eq!(TimeControl::decode(&bank(3601, 0)), None);
+        assert_eq!(TimeControl::decode(&game::TimeControl { limit: None }), None);
+    }
+}
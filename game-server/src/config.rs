@@ -0,0 +1,261 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::Parser;
+use game_core::ai::Difficulty;
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:8080";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_RECONNECT_GRACE_SECS: u64 = 30;
+const DEFAULT_REMATCH_WINDOW_SECS: u64 = 60;
+const DEFAULT_BOT_AFTER_SECS: u64 = 30;
+const DEFAULT_BOT_DIFFICULTY: &str = "heuristic";
+const DEFAULT_RATING_WINDOW: u32 = 100;
+const DEFAULT_RATING_WINDOW_GROWTH: u32 = 10;
+const DEFAULT_DATABASE: &str = "game-server.db";
+const DEFAULT_SESSION_LIFETIME_DAYS: u64 = 180;
+
+/// Command line flags. Each one can also come from the environment,
+/// and anything left unset falls back to the TOML file, then to defaults.
+#[derive(Parser, Debug)]
+#[command(version, about = "Tic-tac-toe game server")]
+struct Cli {
+    /// Optional TOML file with the same settings
+    #[arg(long, env = "GAME_SERVER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address and port to listen on
+    #[arg(long, env = "GAME_SERVER_LISTEN")]
+    listen: Option<String>,
+
+    /// Directory with the built client (wasm, js and assets)
+    #[arg(long, env = "GAME_SERVER_ASSETS")]
+    assets: Option<PathBuf>,
+
+    /// Page served on `/`, defaults to `index.html` inside the assets directory
+    #[arg(long, env = "GAME_SERVER_INDEX")]
+    index: Option<PathBuf>,
+
+    /// One of error, warn, info, debug, trace or off
+    #[arg(long, env = "GAME_SERVER_LOG")]
+    log_level: Option<String>,
+
+    /// Pick sides at random instead of giving X to whoever waited
+    #[arg(long, env = "GAME_SERVER_RANDOM_SIDES")]
+    random_sides: Option<bool>,
+
+    /// Seconds in the queue before a bot takes the empty seat, 0 to wait forever
+    #[arg(long, env = "GAME_SERVER_BOT_AFTER_SECS")]
+    bot_after_secs: Option<u64>,
+
+    /// Difficulty of that bot: random, heuristic or perfect
+    #[arg(long, env = "GAME_SERVER_BOT_DIFFICULTY")]
+    bot_difficulty: Option<String>,
+
+    /// Rating points either way a new search accepts an opponent within
+    #[arg(long, env = "GAME_SERVER_RATING_WINDOW")]
+    rating_window: Option<u32>,
+
+    /// Points the search window widens by for every second in the queue
+    #[arg(long, env = "GAME_SERVER_RATING_WINDOW_GROWTH")]
+    rating_window_growth: Option<u32>,
+
+    /// Seconds a game waits for a disconnected player to come back
+    #[arg(long, env = "GAME_SERVER_RECONNECT_GRACE_SECS")]
+    reconnect_grace_secs: Option<u64>,
+
+    /// Seconds both players have to ask for a rematch after a game
+    #[arg(long, env = "GAME_SERVER_REMATCH_WINDOW_SECS")]
+    rematch_window_secs: Option<u64>,
+
+    /// SQLite file with player profiles, logins and finished games, created if missing
+    #[arg(long, env = "GAME_SERVER_DATABASE")]
+    database: Option<PathBuf>,
+
+    /// Mark the session cookie Secure; turn on when players reach the server over https, directly or through a proxy
+    #[arg(long, env = "GAME_SERVER_SECURE_COOKIE")]
+    secure_cookie: Option<bool>,
+
+    /// Days a login lasts without being used
+    #[arg(long, env = "GAME_SERVER_SESSION_LIFETIME_DAYS")]
+    session_lifetime_days: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    listen: Option<String>,
+    assets: Option<PathBuf>,
+    index: Option<PathBuf>,
+    log_level: Option<String>,
+    database: Option<PathBuf>,
+    matchmaking: FileMatchmaking,
+    session: FileSession,
+    accounts: FileAccounts
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileMatchmaking {
+    random_sides: Option<bool>,
+    bot_after_secs: Option<u64>,
+    bot_difficulty: Option<String>,
+    rating_window: Option<u32>,
+    rating_window_growth: Option<u32>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileSession {
+    reconnect_grace_secs: Option<u64>,
+    rematch_window_secs: Option<u64>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct FileAccounts {
+    secure_cookie: Option<bool>,
+    session_lifetime_days: Option<u64>
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub assets: PathBuf,
+    pub index: PathBuf,
+    pub log_level: LevelFilter,
+    pub database: PathBuf,
+    pub matchmaking: MatchmakingConfig,
+    pub session: SessionConfig,
+    pub accounts: AccountsConfig
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchmakingConfig {
+    pub random_sides: bool,
+    // None when players should wait for a human however long it takes.
+    pub bot_after: Option<Duration>,
+    pub bot_difficulty: Difficulty,
+    // Rating points either way, widened by `rating_window_growth` every second.
+    pub rating_window: u32,
+    pub rating_window_growth: u32
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    pub reconnect_grace: Duration,
+    pub rematch_window: Duration
+}
+
+#[derive(Clone, Debug)]
+pub struct AccountsConfig {
+    // Off by default since the server itself only speaks plain http, and
+    // browsers drop Secure cookies sent over it to anything but localhost.
+    pub secure_cookie: bool,
+    pub session_lifetime: Duration
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    ReadFile(PathBuf, std::io::Error),
+    ParseFile(PathBuf, toml::de::Error),
+    Listen(String),
+    LogLevel(String),
+    BotDifficulty(String),
+    MissingDirectory(PathBuf),
+    MissingFile(PathBuf)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::ReadFile(path, err) => write!(f, "cannot read config file {}: {err}", path.display()),
+            ConfigError::ParseFile(path, err) => write!(f, "invalid config file {}: {err}", path.display()),
+            ConfigError::Listen(value) => write!(f, "listen address `{value}` is not of the form ip:port"),
+            ConfigError::LogLevel(value) => write!(f, "unknown log level `{value}`"),
+            ConfigError::BotDifficulty(value) => write!(f, "unknown bot difficulty `{value}`, expected random, heuristic or perfect"),
+            ConfigError::MissingDirectory(path) => write!(f, "assets directory {} does not exist", path.display()),
+            ConfigError::MissingFile(path) => write!(f, "index page {} does not exist", path.display())
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads flags, environment and the optional config file, in that
+    /// order of precedence, and checks the result before anything starts.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+        let file = match &cli.config {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default()
+        };
+
+        let listen = cli.listen.or(file.listen).unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+        let listen = listen.parse().map_err(|_| ConfigError::Listen(listen))?;
+
+        let log_level = cli.log_level.or(file.log_level).unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        let log_level = LevelFilter::from_str(&log_level).map_err(|_| ConfigError::LogLevel(log_level))?;
+
+        let assets = cli.assets.or(file.assets).unwrap_or_else(default_assets);
+        if !assets.is_dir() {
+            return Err(ConfigError::MissingDirectory(assets));
+        }
+        let index = cli.index.or(file.index).unwrap_or_else(|| assets.join("index.html"));
+        if !index.is_file() {
+            return Err(ConfigError::MissingFile(index));
+        }
+
+        let bot_after_secs = cli.bot_after_secs.or(file.matchmaking.bot_after_secs).unwrap_or(DEFAULT_BOT_AFTER_SECS);
+        let bot_difficulty = cli.bot_difficulty.or(file.matchmaking.bot_difficulty).unwrap_or_else(|| DEFAULT_BOT_DIFFICULTY.to_string());
+        let matchmaking = MatchmakingConfig {
+            random_sides: cli.random_sides.or(file.matchmaking.random_sides).unwrap_or_default(),
+            bot_after: (bot_after_secs > 0).then(|| Duration::from_secs(bot_after_secs)),
+            bot_difficulty: parse_difficulty(&bot_difficulty).ok_or(ConfigError::BotDifficulty(bot_difficulty))?,
+            rating_window: cli.rating_window.or(file.matchmaking.rating_window).unwrap_or(DEFAULT_RATING_WINDOW),
+            rating_window_growth: cli.rating_window_growth.or(file.matchmaking.rating_window_growth).unwrap_or(DEFAULT_RATING_WINDOW_GROWTH)
+        };
+
+        let session = SessionConfig {
+            reconnect_grace: Duration::from_secs(
+                cli.reconnect_grace_secs.or(file.session.reconnect_grace_secs).unwrap_or(DEFAULT_RECONNECT_GRACE_SECS)
+            ),
+            rematch_window: Duration::from_secs(
+                cli.rematch_window_secs.or(file.session.rematch_window_secs).unwrap_or(DEFAULT_REMATCH_WINDOW_SECS)
+            )
+        };
+
+        let session_lifetime_days = cli.session_lifetime_days.or(file.accounts.session_lifetime_days).unwrap_or(DEFAULT_SESSION_LIFETIME_DAYS);
+        let database = cli.database.or(file.database).unwrap_or_else(|| PathBuf::from(DEFAULT_DATABASE));
+        let accounts = AccountsConfig {
+            secure_cookie: cli.secure_cookie.or(file.accounts.secure_cookie).unwrap_or_default(),
+            session_lifetime: Duration::from_secs(session_lifetime_days.saturating_mul(24 * 60 * 60))
+        };
+
+        Ok(Config { listen, assets, index, log_level, database, matchmaking, session, accounts })
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|err| ConfigError::ReadFile(path.to_path_buf(), err))?;
+    toml::from_str(&contents).map_err(|err| ConfigError::ParseFile(path.to_path_buf(), err))
+}
+
+fn parse_difficulty(value: &str) -> Option<Difficulty> {
+    match value.to_ascii_lowercase().as_str() {
+        "random" => Some(Difficulty::Random),
+        "heuristic" => Some(Difficulty::Heuristic),
+        "perfect" => Some(Difficulty::Perfect),
+        _ => None
+    }
+}
+
+fn default_assets() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("game-client").join("static_server")
+}
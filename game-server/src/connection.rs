@@ -0,0 +1,350 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use futures_util::stream::SplitStream;
+use futures_util::{SinkExt, Stream, StreamExt};
+use game_core::{Player, Variant};
+use messages::capability;
+use messages::game::client_message::Message as Client_Message;
+use messages::game::server_message::Message as Com_Message;
+use messages::game::{self, ClientMessage, ErrorCode, GameNotFound, HelloRejected, RoomNotFound, ServerMessage, Welcome};
+use messages::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use prost::Message as _;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+use tracing::{debug, info, warn};
+
+use crate::accounts::Profile;
+use crate::clock::TimeControl;
+use crate::registry::PlayerId;
+use crate::session::{Connection, MatchRules, SessionCommand, SessionHandle};
+use crate::AppState;
+
+static CONNECTION_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+// How long a new socket has to say Hello before we hang up.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Clients may send this many messages at once, and one more every
+// `MESSAGE_INTERVAL` after that. Anything faster is refused.
+const MESSAGE_BURST: u32 = 10;
+const MESSAGE_INTERVAL: Duration = Duration::from_millis(200);
+
+const OUTDATED: &str = "This page is out of date, reload it to keep playing";
+
+// Everything this server offers beyond the bare protocol.
+const CAPABILITIES: [&str; 4] = [capability::RATINGS, capability::REMATCH, capability::SPECTATING, capability::CLOCKS];
+
+pub async fn handle_socket(mut socket: WebSocket, who: SocketAddr,
+    state: Arc<AppState>, profile: Profile
+) {
+    let this_player = profile.id;
+    if !handshake(&mut socket).await {
+        info!("player {this_player} ({who}) was turned away at the handshake");
+        return;
+    }
+    let connection_id = CONNECTION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let (outbound, mut session_events) = mpsc::channel::<ServerMessage>(16);
+    let connection = Connection::new(connection_id, outbound);
+
+    let (mut sender, receiver) = socket.split();
+    let mut requests = ClientRequests::new(receiver, &connection);
+
+    let mut send_task = tokio::spawn(async move {
+        while let Some(message) = session_events.recv().await {
+            let bytes = Bytes::from(message.encode_to_vec());
+            if sender.send(Message::Binary(bytes)).await.is_err() {
+                return;
+            }
+        }
+        // The session let go of us, so the game is over for this socket.
+        let close = CloseFrame { code: close_code::NORMAL, reason: "game over".into() };
+        sender.send(Message::Close(Some(close))).await.ok();
+    });
+
+    // Back into a running game, or whatever the client asks for
+    let (me, session) = match rejoin(&state, this_player, connection).await {
+        Ok(seat) => {
+            info!("player {this_player} ({who}) rejoined as {:?}", seat.0);
+            seat
+        },
+        Err(connection) => {
+            match choose_game(&state, &profile, connection, &mut requests).await {
+                Some(Role::Player(me, session)) => {
+                    info!("matched player {this_player} ({who}) as {me:?}");
+                    (me, session)
+                },
+                Some(Role::Spectator(session)) => {
+                    // The session feeds the send task until the game ends, and
+                    // all a spectator may ask for is to be caught up.
+                    let follow = async {
+                        while let Some((message, _)) = requests.next().await {
+                            if let Client_Message::ResyncRequest(_) = message {
+                                session.send(SessionCommand::Resync { connection: connection_id }).await;
+                            }
+                        }
+                    };
+                    tokio::select! {
+                        _ = &mut send_task => {},
+                        _ = follow => send_task.abort()
+                    }
+                    debug!("spectator {this_player} ({who}) stopped watching");
+                    return;
+                },
+                None => {
+                    info!("player {this_player} ({who}) left before being matched");
+                    state.matchmaker.leave(connection_id).await;
+                    send_task.abort();
+                    return;
+                }
+            }
+        }
+    };
+
+    // Client communication
+    let session_for_recv = session.clone();
+    let mut recv_task = tokio::spawn(async move {
+        while let Some((message, seq)) = requests.next().await {
+            let command = match message {
+                Client_Message::PlayerMove(player_move) => SessionCommand::Move { player: me, player_move, seq },
+                Client_Message::RematchRequest(_) => SessionCommand::Rematch { player: me },
+                Client_Message::ResyncRequest(_) => SessionCommand::Resync { connection: connection_id },
+                _ => continue
+            };
+            if !session_for_recv.send(command).await {
+                break;
+            }
+        }
+    });
+
+    tokio::select! {
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort()
+    }
+    session.send(SessionCommand::Disconnect { player: me, connection: connection_id }).await;
+
+    debug!("socket of player {this_player} ({who}) closed");
+}
+
+/// Waits for the client's Hello and welcomes it if we speak its
+/// version. Otherwise tells it why not and closes the socket.
+async fn handshake(socket: &mut WebSocket) -> bool {
+    let hello = match timeout(HELLO_TIMEOUT, next_message(socket)).await {
+        Ok(Some(Ok((Client_Message::Hello(hello), _)))) => hello,
+        // Builds from before the handshake start right in with a request.
+        Ok(Some(_)) => {
+            reject(socket, OUTDATED).await;
+            return false;
+        },
+        Ok(None) | Err(_) => return false
+    };
+    debug!("client speaks version {} with {:?}", hello.protocol_version, hello.capabilities);
+
+    if hello.protocol_version < MIN_PROTOCOL_VERSION {
+        reject(socket, OUTDATED).await;
+        return false;
+    }
+    if hello.protocol_version > PROTOCOL_VERSION {
+        reject(socket, "The server is older than this page, try again in a few minutes").await;
+        return false;
+    }
+    let welcome = Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.iter().map(|name| name.to_string()).collect()
+    };
+    let welcome = ServerMessage { seq: 0, message: Some(Com_Message::Welcome(welcome)) };
+    socket.send(Message::Binary(Bytes::from(welcome.encode_to_vec()))).await.is_ok()
+}
+
+async fn reject(socket: &mut WebSocket, reason: &str) {
+    let rejected = HelloRejected {
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        protocol_version: PROTOCOL_VERSION,
+        reason: reason.to_string()
+    };
+    let rejected = ServerMessage { seq: 0, message: Some(Com_Message::HelloRejected(rejected)) };
+    if socket.send(Message::Binary(Bytes::from(rejected.encode_to_vec()))).await.is_ok() {
+        // Retrying won't help, which the client can tell from the code.
+        let close = CloseFrame { code: close_code::POLICY, reason: "incompatible protocol version".into() };
+        socket.send(Message::Close(Some(close))).await.ok();
+    }
+}
+
+async fn rejoin(state: &AppState, player_id: PlayerId, connection: Connection) -> Result<(Player, SessionHandle), Connection> {
+    let Some(seat) = state.registry.find(player_id).await else {
+        return Err(connection);
+    };
+    seat.session.reconnect(seat.player, connection).await?;
+    Ok((seat.player, seat.session))
+}
+
+enum Role {
+    Player(Player, SessionHandle),
+    Spectator(SessionHandle)
+}
+
+/// Follows the client's requests until it is seated in a game or
+/// watching one, or returns `None` if it goes away first.
+async fn choose_game(
+    state: &AppState,
+    player: &Profile,
+    mut connection: Connection,
+    requests: &mut ClientRequests
+) -> Option<Role> {
+    loop {
+        let (message, _) = requests.next().await?;
+        match message {
+            Client_Message::FindGame(find) => {
+                let rules = requested_rules(find.rules.as_ref());
+                return tokio::select! {
+                    seat = state.matchmaker.find_game(player, rules, connection) => seat.map(|(me, session)| Role::Player(me, session)),
+                    _ = wait_for_close(requests) => None
+                };
+            },
+            Client_Message::CreateRoom(create) => {
+                let rules = requested_rules(create.rules.as_ref());
+                return tokio::select! {
+                    seat = state.matchmaker.create_room(player, rules, connection) => seat.map(|(me, session)| Role::Player(me, session)),
+                    _ = wait_for_close(requests) => None
+                };
+            },
+            Client_Message::PlayBot(play) => {
+                let rules = requested_rules(play.rules.as_ref());
+                let (me, session) = state.matchmaker.play_bot(player, rules, connection, play.difficulty().into()).await;
+                return Some(Role::Player(me, session));
+            },
+            Client_Message::Spectate(spectate) => {
+                let session = state.registry.find_game(spectate.game_id).await;
+                let watching = match &session {
+                    Some(session) => session.spectate(connection).await,
+                    None => Err(connection)
+                };
+                match watching {
+                    Ok(()) => return session.map(Role::Spectator),
+                    Err(returned) => {
+                        connection = returned;
+                        let not_found = GameNotFound { game_id: spectate.game_id };
+                        connection.outbound.send(ServerMessage { seq: 0, message: Some(Com_Message::GameNotFound(not_found)) }).await.ok();
+                    }
+                }
+            },
+            Client_Message::JoinRoom(join) => {
+                match state.matchmaker.join_room(&join.code, player, connection).await {
+                    Ok((me, session)) => return Some(Role::Player(me, session)),
+                    Err(returned) => {
+                        connection = returned;
+                        let not_found = RoomNotFound { code: join.code };
+                        connection.outbound.send(ServerMessage { seq: 0, message: Some(Com_Message::RoomNotFound(not_found)) }).await.ok();
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The rules the client asked for. Falls back to the classic game for
+/// a variant we can't play, and to no clock for limits we don't allow.
+fn requested_rules(rules: Option<&messages::game::Rules>) -> MatchRules {
+    let variant = messages::decode_variant(rules).unwrap_or_else(|| {
+        warn!("unsupported rules {rules:?}, playing the classic game instead");
+        Variant::default()
+    });
+    let time_control = rules.and_then(|rules| rules.time_control.as_ref()).and_then(|requested| {
+        let decoded = TimeControl::decode(requested);
+        if decoded.is_none() && requested.limit.is_some() {
+            warn!("unsupported time control {requested:?}, playing without a clock");
+        }
+        decoded
+    });
+    MatchRules { variant, time_control }
+}
+
+/// What the client asks for once it is past the handshake. Messages
+/// that can't be decoded or come too fast are answered with an error
+/// and skipped.
+struct ClientRequests {
+    receiver: SplitStream<WebSocket>,
+    // Weak, so the send task still ends once the session lets go.
+    outbound: mpsc::WeakSender<ServerMessage>,
+    // Messages the client may still send right away.
+    allowance: u32,
+    refilled: Instant
+}
+
+impl ClientRequests {
+    fn new(receiver: SplitStream<WebSocket>, connection: &Connection) -> Self {
+        ClientRequests {
+            receiver,
+            outbound: connection.outbound.downgrade(),
+            allowance: MESSAGE_BURST,
+            refilled: Instant::now()
+        }
+    }
+
+    /// Next request worth acting on and its seq, or `None` once the
+    /// client goes away.
+    async fn next(&mut self) -> Option<(Client_Message, u32)> {
+        loop {
+            let refused = match next_message(&mut self.receiver).await? {
+                Ok(_) if !self.allow(Instant::now()) => ErrorCode::RateLimited,
+                Ok(message) => return Some(message),
+                Err(code) => code
+            };
+            if let Some(outbound) = self.outbound.upgrade() {
+                let error = game::Error { code: refused as i32, rejected_move: None };
+                outbound.send(ServerMessage { seq: 0, message: Some(Com_Message::Error(error)) }).await.ok();
+            }
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        let earned = now.saturating_duration_since(self.refilled).as_millis() / MESSAGE_INTERVAL.as_millis();
+        if earned > 0 {
+            let earned = u32::try_from(earned).unwrap_or(u32::MAX);
+            self.allowance = self.allowance.saturating_add(earned).min(MESSAGE_BURST);
+            self.refilled += MESSAGE_INTERVAL.saturating_mul(earned);
+        }
+        let Some(left) = self.allowance.checked_sub(1) else {
+            return false;
+        };
+        self.allowance = left;
+        true
+    }
+}
+
+/// Next message from the client and its seq, or `None` once it goes
+/// away. Ones that don't decode to anything come back as errors.
+async fn next_message<S>(receiver: &mut S) -> Option<Result<(Client_Message, u32), ErrorCode>>
+where
+    S: Stream<Item = Result<Message, axum::Error>> + Unpin
+{
+    while let Some(Ok(msg)) = receiver.next().await {
+        match msg {
+            Message::Binary(bytes) => {
+                return match ClientMessage::decode(&*bytes) {
+                    Ok(ClientMessage { seq, message: Some(message) }) => Some(Ok((message, seq))),
+                    Ok(_) | Err(_) => {
+                        warn!("failed to decode protobuf message");
+                        Some(Err(ErrorCode::MalformedMessage))
+                    }
+                };
+            },
+            Message::Text(_) => {
+                warn!("text is not supported anymore");
+            },
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolves once the client goes away. Anything it sends meanwhile is ignored.
+async fn wait_for_close(requests: &mut ClientRequests) {
+    while requests.next().await.is_some() {}
+}
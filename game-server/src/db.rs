@@ -0,0 +1,127 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+use tracing::info;
+
+// Applied in order, each once; `PRAGMA user_version` counts how many ran.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE players (
+        id INTEGER PRIMARY KEY,
+        display_name TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE sessions (
+        token_hash BLOB PRIMARY KEY,
+        player_id INTEGER NOT NULL REFERENCES players(id),
+        last_seen INTEGER NOT NULL
+    );",
+    "CREATE TABLE games (
+        id INTEGER PRIMARY KEY,
+        variant TEXT NOT NULL,
+        time_control TEXT,
+        x_player INTEGER REFERENCES players(id),
+        o_player INTEGER REFERENCES players(id),
+        x_label TEXT NOT NULL,
+        o_label TEXT NOT NULL,
+        outcome TEXT NOT NULL,
+        winner TEXT,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL
+    );
+    CREATE INDEX games_by_x_player ON games (x_player, id);
+    CREATE INDEX games_by_o_player ON games (o_player, id);
+    CREATE TABLE moves (
+        game_id INTEGER NOT NULL REFERENCES games(id),
+        ply INTEGER NOT NULL,
+        player TEXT NOT NULL,
+        cell INTEGER NOT NULL,
+        piece TEXT NOT NULL,
+        at_ms INTEGER NOT NULL,
+        PRIMARY KEY (game_id, ply)
+    );",
+    "ALTER TABLE players ADD COLUMN rating INTEGER NOT NULL DEFAULT 1500;
+    ALTER TABLE players ADD COLUMN rated_games INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE games ADD COLUMN x_rating_delta INTEGER;
+    ALTER TABLE games ADD COLUMN o_rating_delta INTEGER;",
+    "ALTER TABLE games ADD COLUMN replay BLOB;",
+    "CREATE INDEX sessions_by_player ON sessions (player_id);
+    CREATE INDEX sessions_by_last_seen ON sessions (last_seen);",
+    // Variants were stored by their display text, like "3x3, 3 in a row",
+    // before they got names that don't change, like "standard-3-3".
+    "UPDATE games SET variant = 'standard-'
+        || substr(variant, 1, instr(variant, 'x') - 1) || '-'
+        || substr(variant, instr(variant, ', ') + 2, instr(variant, ' in a row') - instr(variant, ', ') - 2)
+    WHERE variant LIKE '%x%, % in a row';
+    UPDATE games SET variant = 'misere' WHERE variant = 'misère';"
+];
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Sqlite(rusqlite::Error),
+    // The blocking database task panicked or was cancelled.
+    Task
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Sqlite(err) => write!(f, "database error: {err}"),
+            DatabaseError::Task => write!(f, "database task failed")
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// The server's SQLite file, shared by the stores that keep things
+/// across restarts. Cheap to clone.
+#[derive(Clone)]
+pub struct Database {
+    connection: Arc<Mutex<Connection>>
+}
+
+impl Database {
+    /// Opens the file, creating it and bringing its tables up to date.
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let mut connection = Connection::open(path).map_err(DatabaseError::Sqlite)?;
+        connection.pragma_update(None, "foreign_keys", true).map_err(DatabaseError::Sqlite)?;
+        migrate(&mut connection).map_err(DatabaseError::Sqlite)?;
+        info!("database at {}", path.display());
+        Ok(Database { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// Runs `query` on a blocking thread, since SQLite calls block.
+    pub async fn run<T, F>(&self, query: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static
+    {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&mut connection)
+        })
+        .await
+        .map_err(|_| DatabaseError::Task)?
+        .map_err(DatabaseError::Sqlite)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let applied: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let tx = connection.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Milliseconds since the Unix epoch, how the database keeps time.
+pub fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
@@ -0,0 +1,413 @@
+use std::time::{Duration, SystemTime};
+
+use game_core::{Move, Piece, Player};
+use messages::game::{GameOutcome, PlayerType, Replay, ReplayMove};
+use prost::Message;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+use tracing::{debug, error};
+
+use crate::db::{unix_millis, Database, DatabaseError};
+use crate::rating::{self, RatingChange};
+use crate::registry::PlayerId;
+use crate::session::MatchRules;
+
+const GAME_COLUMNS: &str =
+    "id, variant, time_control, x_player, x_label, o_player, o_label, outcome, winner, started_at, finished_at, x_rating_delta, o_rating_delta";
+
+/// A finished game as the session saw it, ready to be stored.
+pub struct GameRecord {
+    pub rules: MatchRules,
+    pub x: RecordedPlayer,
+    pub o: RecordedPlayer,
+    pub outcome: GameOutcome,
+    pub winner: Option<Player>,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    pub moves: Vec<RecordedMove>
+}
+
+pub struct RecordedPlayer {
+    // `None` for bots.
+    pub id: Option<PlayerId>,
+    pub label: String
+}
+
+pub struct RecordedMove {
+    pub mv: Move,
+    // Since the game started.
+    pub at: Duration
+}
+
+#[derive(Serialize)]
+pub struct GameSummary {
+    id: i64,
+    // Like "standard-3-3", see `messages::variant_slug`.
+    variant: String,
+    time_control: Option<String>,
+    x: PlayerEntry,
+    o: PlayerEntry,
+    outcome: String,
+    winner: Option<String>,
+    // Milliseconds since the Unix epoch.
+    started_at: i64,
+    finished_at: i64
+}
+
+#[derive(Serialize)]
+pub struct PlayerEntry {
+    id: Option<PlayerId>,
+    label: String,
+    // How far the game moved their rating, `None` for unrated games.
+    rating_delta: Option<i32>
+}
+
+#[derive(Serialize)]
+pub struct GameDetails {
+    #[serde(flatten)]
+    summary: GameSummary,
+    moves: Vec<MoveEntry>
+}
+
+/// Cells are numbered the way the variant's board numbers them, row by
+/// row across the whole grid for ultimate.
+#[derive(Serialize)]
+pub struct MoveEntry {
+    ply: u32,
+    player: String,
+    cell: u32,
+    // The mark put down, or the number in numerical.
+    piece: String,
+    // Since the game started.
+    at_ms: i64
+}
+
+/// Newest first, `limit` at a time, starting below the id in `before`.
+pub struct Page {
+    pub limit: u32,
+    pub before: Option<i64>
+}
+
+/// Every finished game, kept for dashboards and anyone curious.
+pub struct MatchHistory {
+    db: Database
+}
+
+impl MatchHistory {
+    pub fn new(db: Database) -> Self {
+        MatchHistory { db }
+    }
+
+    /// Stores the game and rates it when two different players sat in
+    /// it. Returns the rating changes for X and O if it was rated. A
+    /// failure is only logged, since there's nothing the players could
+    /// do about it.
+    pub async fn record(&self, game: GameRecord) -> Option<[RatingChange; 2]> {
+        match self.db.run(move |db| insert(db, &game)).await {
+            Ok((id, changes)) => {
+                debug!("recorded game {id}");
+                changes
+            },
+            Err(err) => {
+                error!("cannot record a finished game: {err}");
+                None
+            }
+        }
+    }
+
+    pub async fn games(&self, page: Page) -> Result<Vec<GameSummary>, DatabaseError> {
+        self.db.run(move |db| {
+            let sql = format!("SELECT {GAME_COLUMNS} FROM games WHERE (?1 IS NULL OR id < ?1) ORDER BY id DESC LIMIT ?2");
+            let mut statement = db.prepare(&sql)?;
+            let games = statement.query_map(params![page.before, page.limit], summary)?;
+            games.collect()
+        }).await
+    }
+
+    /// Games the player sat in, on either side.
+    pub async fn player_games(&self, player_id: PlayerId, page: Page) -> Result<Vec<GameSummary>, DatabaseError> {
+        self.db.run(move |db| {
+            let sql = format!(
+                "SELECT {GAME_COLUMNS} FROM games WHERE (x_player = ?3 OR o_player = ?3) AND (?1 IS NULL OR id < ?1)
+                 ORDER BY id DESC LIMIT ?2"
+            );
+            let mut statement = db.prepare(&sql)?;
+            let games = statement.query_map(params![page.before, page.limit, player_id as i64], summary)?;
+            games.collect()
+        }).await
+    }
+
+    /// The game encoded as a `Replay` message, or `None` if there is no
+    /// such game or it was recorded before replays were kept.
+    pub async fn replay(&self, id: i64) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.db.run(move |db| {
+            let replay = db.query_row("SELECT replay FROM games WHERE id = ?1", params![id], |row| row.get(0)).optional()?;
+            Ok(replay.flatten())
+        }).await
+    }
+
+    /// The game with all its moves, or `None` if there is no such game.
+    pub async fn game(&self, id: i64) -> Result<Option<GameDetails>, DatabaseError> {
+        self.db.run(move |db| {
+            let sql = format!("SELECT {GAME_COLUMNS} FROM games WHERE id = ?1");
+            let Some(summary) = db.query_row(&sql, params![id], summary).optional()? else {
+                return Ok(None);
+            };
+            let mut statement = db.prepare("SELECT ply, player, cell, piece, at_ms FROM moves WHERE game_id = ?1 ORDER BY ply")?;
+            let moves = statement.query_map(params![id], |row| Ok(MoveEntry {
+                ply: row.get(0)?,
+                player: row.get(1)?,
+                cell: row.get(2)?,
+                piece: row.get(3)?,
+                at_ms: row.get(4)?
+            }))?;
+            Ok(Some(GameDetails { summary, moves: moves.collect::<rusqlite::Result<_>>()? }))
+        }).await
+    }
+}
+
+fn insert(db: &mut rusqlite::Connection, game: &GameRecord) -> rusqlite::Result<(i64, Option<[RatingChange; 2]>)> {
+    let tx = db.transaction()?;
+    let changes = match (game.x.id, game.o.id) {
+        (Some(x), Some(o)) if x != o => Some(rating::rate_game(&tx, x, o, game.winner)?),
+        _ => None
+    };
+    tx.execute(
+        "INSERT INTO games (variant, time_control, x_player, x_label, o_player, o_label, outcome, winner, started_at, finished_at,
+                            x_rating_delta, o_rating_delta, replay)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            messages::variant_slug(game.rules.variant),
+            game.rules.time_control.map(|time_control| time_control.to_string()),
+            game.x.id.map(|id| id as i64),
+            game.x.label,
+            game.o.id.map(|id| id as i64),
+            game.o.label,
+            outcome_name(game.outcome),
+            game.winner.map(player_name),
+            unix_millis(game.started_at),
+            unix_millis(game.finished_at),
+            changes.map(|[x, _]| x.delta),
+            changes.map(|[_, o]| o.delta),
+            replay(game).encode_to_vec()
+        ]
+    )?;
+    let id = tx.last_insert_rowid();
+    for (ply, recorded) in game.moves.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO moves (game_id, ply, player, cell, piece, at_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                ply as u32,
+                player_name(recorded.mv.player),
+                recorded.mv.cell as u32,
+                piece_name(recorded.mv.piece),
+                recorded.at.as_millis() as i64
+            ]
+        )?;
+    }
+    tx.commit()?;
+    Ok((id, changes))
+}
+
+fn replay(game: &GameRecord) -> Replay {
+    Replay {
+        rules: Some(game.rules.into()),
+        x_label: game.x.label.clone(),
+        o_label: game.o.label.clone(),
+        outcome: game.outcome as i32,
+        winner: game.winner.map(|winner| PlayerType::from(winner) as i32),
+        started_at: unix_millis(game.started_at) as u64,
+        moves: game.moves.iter().map(|recorded| ReplayMove {
+            r#move: Some(messages::encode_move(game.rules.variant, recorded.mv)),
+            at_ms: recorded.at.as_millis() as u32
+        }).collect()
+    }
+}
+
+fn summary(row: &Row) -> rusqlite::Result<GameSummary> {
+    Ok(GameSummary {
+        id: row.get(0)?,
+        variant: row.get(1)?,
+        time_control: row.get(2)?,
+        x: PlayerEntry {
+            id: row.get::<_, Option<i64>>(3)?.map(|id| id as PlayerId),
+            label: row.get(4)?,
+            rating_delta: row.get(11)?
+        },
+        o: PlayerEntry {
+            id: row.get::<_, Option<i64>>(5)?.map(|id| id as PlayerId),
+            label: row.get(6)?,
+            rating_delta: row.get(12)?
+        },
+        outcome: row.get(7)?,
+        winner: row.get(8)?,
+        started_at: row.get(9)?,
+        finished_at: row.get(10)?
+    })
+}
+
+fn outcome_name(outcome: GameOutcome) -> &'static str {
+    match outcome {
+        GameOutcome::XWins => "x_wins",
+        GameOutcome::OWins => "o_wins",
+        GameOutcome::Draw => "draw",
+        GameOutcome::Abandoned => "abandoned",
+        GameOutcome::Timeout => "timeout"
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::X => "X",
+        Player::O => "O"
+    }
+}
+
+fn piece_name(piece: Piece) -> String {
+    match piece {
+        Piece::Mark(mark) => player_name(mark).to_string(),
+        Piece::Number(number) => number.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use game_core::{Rules, Variant};
+
+    use super::*;
+    use crate::accounts::AccountStore;
+    use crate::clock::TimeControl;
+
+    struct Fixture {
+        history: MatchHistory,
+        accounts: AccountStore
+    }
+
+    fn fixture() -> Fixture {
+        let db = Database::open(Path::new(":memory:")).unwrap();
+        Fixture {
+            history: MatchHistory::new(db.clone()),
+            accounts: AccountStore::new(db, Duration::from_secs(60))
+        }
+    }
+
+    fn player(id: Option<PlayerId>, label: &str) -> RecordedPlayer {
+        RecordedPlayer { id, label: label.to_string() }
+    }
+
+    // X takes the top row while O answers in the middle one.
+    fn x_wins(x: RecordedPlayer, o: RecordedPlayer) -> GameRecord {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let moves = [(0, Player::X), (3, Player::O), (1, Player::X), (4, Player::O), (2, Player::X)]
+            .into_iter()
+            .enumerate()
+            .map(|(ply, (cell, player))| RecordedMove {
+                mv: Move { player, cell, piece: Piece::Mark(player) },
+                at: Duration::from_millis(ply as u64 * 1500)
+            })
+            .collect();
+        GameRecord {
+            rules: MatchRules { variant: Variant::Standard(Rules::default()), time_control: Some(TimeControl::PerMove(Duration::from_secs(10))) },
+            x,
+            o,
+            outcome: GameOutcome::XWins,
+            winner: Some(Player::X),
+            started_at: start,
+            finished_at: start + Duration::from_secs(6),
+            moves
+        }
+    }
+
+    fn ids(games: &[GameSummary]) -> Vec<i64> {
+        games.iter().map(|game| game.id).collect()
+    }
+
+    #[tokio::test]
+    async fn recorded_game_reads_back() {
+        let fixture = fixture();
+        let (x, _) = fixture.accounts.sign_up().await.unwrap();
+        let (o, _) = fixture.accounts.sign_up().await.unwrap();
+        let changes = fixture.history.record(x_wins(player(Some(x.id), "Ada"), player(Some(o.id), "Bob"))).await;
+        let [x_change, o_change] = changes.expect("two players are rated");
+        assert!(x_change.delta > 0 && o_change.delta < 0);
+
+        let game = fixture.history.game(1).await.unwrap().unwrap();
+        let summary = &game.summary;
+        assert_eq!(summary.variant, "standard-3-3");
+        assert_eq!(summary.time_control.as_deref(), Some("10s per move"));
+        assert_eq!((summary.x.id, summary.x.label.as_str(), summary.x.rating_delta), (Some(x.id), "Ada", Some(x_change.delta)));
+        assert_eq!((summary.o.id, summary.o.label.as_str(), summary.o.rating_delta), (Some(o.id), "Bob", Some(o_change.delta)));
+        assert_eq!(summary.outcome, "x_wins");
+        assert_eq!(summary.winner.as_deref(), Some("X"));
+        assert_eq!(summary.finished_at - summary.started_at, 6000);
+        let moves: Vec<_> = game.moves.iter().map(|entry| (entry.ply, entry.player.as_str(), entry.cell, entry.piece.as_str(), entry.at_ms)).collect();
+        assert_eq!(moves, [(0, "X", 0, "X", 0), (1, "O", 3, "O", 1500), (2, "X", 1, "X", 3000), (3, "O", 4, "O", 4500), (4, "X", 2, "X", 6000)]);
+
+        assert!(fixture.history.game(2).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn replay_decodes_to_the_same_game() {
+        let fixture = fixture();
+        let record = x_wins(player(None, "Bot (random)"), player(None, "Bot (perfect)"));
+        let expected: Vec<_> = record.moves.iter().map(|recorded| recorded.mv).collect();
+        assert!(fixture.history.record(record).await.is_none(), "bot games aren't rated");
+
+        let bytes = fixture.history.replay(1).await.unwrap().unwrap();
+        let replay = Replay::decode(&*bytes).unwrap();
+        assert_eq!(replay.x_label, "Bot (random)");
+        assert_eq!(replay.outcome(), GameOutcome::XWins);
+        let variant = messages::decode_variant(replay.rules.as_ref()).unwrap();
+        let mut board = game_core::Game::new(variant);
+        for (recorded, expected) in replay.moves.iter().zip(&expected) {
+            let mv = messages::decode_move(recorded.r#move.as_ref().unwrap(), board.turn()).unwrap();
+            assert_eq!(mv, *expected);
+            board.apply_move(mv).unwrap();
+        }
+        assert_eq!(board.winning_line().map(|(winner, _)| winner), Some(Player::X));
+        assert!(fixture.history.replay(2).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn same_player_on_both_sides_is_not_rated() {
+        let fixture = fixture();
+        let (me, _) = fixture.accounts.sign_up().await.unwrap();
+        assert!(fixture.history.record(x_wins(player(Some(me.id), "Me"), player(Some(me.id), "Me"))).await.is_none());
+        assert_eq!(fixture.accounts.profile(me.id).await.unwrap().unwrap().rated_games, 0);
+    }
+
+    #[tokio::test]
+    async fn pages_go_newest_first() {
+        let fixture = fixture();
+        for _ in 0..5 {
+            fixture.history.record(x_wins(player(None, "A"), player(None, "B"))).await;
+        }
+        let first = fixture.history.games(Page { limit: 2, before: None }).await.unwrap();
+        assert_eq!(ids(&first), [5, 4]);
+        let second = fixture.history.games(Page { limit: 2, before: Some(4) }).await.unwrap();
+        assert_eq!(ids(&second), [3, 2]);
+        let last = fixture.history.games(Page { limit: 2, before: Some(2) }).await.unwrap();
+        assert_eq!(ids(&last), [1]);
+        assert!(fixture.history.games(Page { limit: 2, before: Some(1) }).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn player_games_cover_both_sides() {
+        let fixture = fixture();
+        let (ada, _) = fixture.accounts.sign_up().await.unwrap();
+        let (bob, _) = fixture.accounts.sign_up().await.unwrap();
+        fixture.history.record(x_wins(player(Some(ada.id), "Ada"), player(None, "Bot"))).await;
+        fixture.history.record(x_wins(player(Some(bob.id), "Bob"), player(None, "Bot"))).await;
+        fixture.history.record(x_wins(player(None, "Bot"), player(Some(ada.id), "Ada"))).await;
+        fixture.history.record(x_wins(player(Some(bob.id), "Bob"), player(Some(ada.id), "Ada"))).await;
+
+        let all = Page { limit: 50, before: None };
+        assert_eq!(ids(&fixture.history.player_games(ada.id, all).await.unwrap()), [4, 3, 1]);
+        assert_eq!(ids(&fixture.history.player_games(ada.id, Page { limit: 1, before: Some(4) }).await.unwrap()), [3]);
+        assert_eq!(ids(&fixture.history.player_games(bob.id, Page { limit: 50, before: None }).await.unwrap()), [4, 2]);
+        assert!(fixture.history.player_games(bob.id + 100, Page { limit: 50, before: None }).await.unwrap().is_empty());
+    }
+}
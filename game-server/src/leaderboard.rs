@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use game_core::Variant;
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+
+use crate::accounts;
+use crate::db::{unix_millis, Database, DatabaseError};
+use crate::registry::PlayerId;
+
+// Each rated game once per side, as W, D or L for that side's player.
+// ?1 is the variant and ?2 the earliest finish, both optional.
+const RESULTS: &str = "
+    results AS (
+        SELECT id AS game_id, x_player AS player, CASE winner WHEN 'X' THEN 'W' WHEN 'O' THEN 'L' ELSE 'D' END AS result
+        FROM games WHERE x_rating_delta IS NOT NULL AND (?1 IS NULL OR variant = ?1) AND (?2 IS NULL OR finished_at >= ?2)
+        UNION ALL
+        SELECT id, o_player, CASE winner WHEN 'O' THEN 'W' WHEN 'X' THEN 'L' ELSE 'D' END
+        FROM games WHERE o_rating_delta IS NOT NULL AND (?1 IS NULL OR variant = ?1) AND (?2 IS NULL OR finished_at >= ?2)
+    )";
+
+// Everyone with a result above, ranked by rating, ties sharing a rank.
+const STANDINGS: &str = "
+    standings AS (
+        SELECT players.id, players.display_name, players.rating, COUNT(*) AS games,
+               SUM(result = 'W') AS wins, SUM(result = 'D') AS draws, SUM(result = 'L') AS losses,
+               RANK() OVER (ORDER BY players.rating DESC) AS rank
+        FROM results JOIN players ON players.id = results.player
+        GROUP BY players.id
+    )";
+
+const STANDING_COLUMNS: &str = "rank, id, display_name, rating, games, wins, draws, losses";
+
+// How many of a player's latest results make up their form.
+const FORM_GAMES: u32 = 5;
+
+/// Which games count. Ratings are shared by all variants, so the
+/// filter only picks who shows up and the record next to their name.
+pub struct LeaderboardFilter {
+    pub variant: Option<Variant>,
+    pub since: Option<SystemTime>
+}
+
+#[derive(Serialize)]
+pub struct Standing {
+    rank: u32,
+    id: PlayerId,
+    label: String,
+    rating: i32,
+    games: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+    // Latest results first, like "WWDL".
+    form: String
+}
+
+/// Players ranked by rating among those with rated games.
+pub struct Leaderboard {
+    db: Database
+}
+
+impl Leaderboard {
+    pub fn new(db: Database) -> Self {
+        Leaderboard { db }
+    }
+
+    /// `limit` standings from the `offset`th on, best first.
+    pub async fn top(&self, filter: LeaderboardFilter, limit: u32, offset: u32) -> Result<Vec<Standing>, DatabaseError> {
+        self.db.run(move |db| {
+            let (variant, since) = filter.params();
+            let sql = format!("WITH {RESULTS}, {STANDINGS} SELECT {STANDING_COLUMNS} FROM standings ORDER BY rank, id LIMIT ?3 OFFSET ?4");
+            let mut statement = db.prepare(&sql)?;
+            let standings = statement.query_map(params![variant, since, limit, offset], standing)?.collect::<rusqlite::Result<Vec<_>>>()?;
+            with_form(db, &variant, since, standings)
+        }).await
+    }
+
+    /// Where the player stands, or `None` if they have no rated games
+    /// that pass the filter.
+    pub async fn standing(&self, player_id: PlayerId, filter: LeaderboardFilter) -> Result<Option<Standing>, DatabaseError> {
+        self.db.run(move |db| {
+            let (variant, since) = filter.params();
+            let sql = format!("WITH {RESULTS}, {STANDINGS} SELECT {STANDING_COLUMNS} FROM standings WHERE id = ?3");
+            let Some(standing) = db.query_row(&sql, params![variant, since, player_id as i64], standing).optional()? else {
+                return Ok(None);
+            };
+            Ok(with_form(db, &variant, since, vec![standing])?.pop())
+        }).await
+    }
+}
+
+impl LeaderboardFilter {
+    fn params(&self) -> (Option<String>, Option<i64>) {
+        (self.variant.map(messages::variant_slug), self.since.map(unix_millis))
+    }
+}
+
+/// Fills in the form of each standing from the same filtered results.
+fn with_form(
+    db: &rusqlite::Connection,
+    variant: &Option<String>,
+    since: Option<i64>,
+    mut standings: Vec<Standing>
+) -> rusqlite::Result<Vec<Standing>> {
+    // Ids come from the database, so they are safe to put in the query.
+    let players = standings.iter().map(|standing| standing.id.to_string()).collect::<Vec<_>>().join(", ");
+    let sql = format!(
+        "WITH {RESULTS}, latest AS (
+             SELECT player, result, ROW_NUMBER() OVER (PARTITION BY player ORDER BY game_id DESC) AS n
+             FROM results WHERE player IN ({players})
+         )
+         SELECT player, result FROM latest WHERE n <= ?3 ORDER BY player, n"
+    );
+    let mut statement = db.prepare(&sql)?;
+    let mut forms: HashMap<PlayerId, String> = HashMap::new();
+    let rows = statement.query_map(params![variant, since, FORM_GAMES], |row| {
+        Ok((row.get::<_, i64>(0)? as PlayerId, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (player, result) = row?;
+        forms.entry(player).or_default().push_str(&result);
+    }
+    for standing in &mut standings {
+        standing.form = forms.remove(&standing.id).unwrap_or_default();
+    }
+    Ok(standings)
+}
+
+fn standing(row: &Row) -> rusqlite::Result<Standing> {
+    let id = row.get::<_, i64>(1)? as PlayerId;
+    Ok(Standing {
+        rank: row.get(0)?,
+        id,
+        label: accounts::label(id, row.get::<_, Option<String>>(2)?.as_deref()),
+        rating: row.get(3)?,
+        games: row.get(4)?,
+        wins: row.get(5)?,
+        draws: row.get(6)?,
+        losses: row.get(7)?,
+        form: String::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use game_core::{Move, Player};
+    use messages::game::GameOutcome;
+
+    use super::*;
+    use crate::accounts::AccountStore;
+    use crate::history::{GameRecord, MatchHistory, RecordedMove, RecordedPlayer};
+    use crate::session::MatchRules;
+
+    // X wins on the top row of any board of at least 3x3.
+    fn x_wins(variant: Variant, x: PlayerId, o: PlayerId) -> GameRecord {
+        let moves = [0, 3, 1, 4, 2].into_iter().enumerate().map(|(ply, cell)| {
+            let player = if ply % 2 == 0 { Player::X } else { Player::O };
+            RecordedMove { mv: Move::new(cell, player), at: Duration::from_secs(ply as u64) }
+        });
+        GameRecord {
+            rules: MatchRules { variant, time_control: None },
+            x: RecordedPlayer { id: Some(x), label: String::new() },
+            o: RecordedPlayer { id: Some(o), label: String::new() },
+            outcome: GameOutcome::XWins,
+            winner: Some(Player::X),
+            started_at: SystemTime::now(),
+            finished_at: SystemTime::now(),
+            moves: moves.collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_by_variant() {
+        let db = Database::open(Path::new(":memory:")).unwrap();
+        let (accounts, history, leaderboard) = (AccountStore::new(db.clone(), Duration::from_secs(60)), MatchHistory::new(db.clone()), Leaderboard::new(db));
+        let (ada, _) = accounts.sign_up().await.unwrap();
+        let (bob, _) = accounts.sign_up().await.unwrap();
+        let (cid, _) = accounts.sign_up().await.unwrap();
+        history.record(x_wins(Variant::default(), ada.id, bob.id)).await.unwrap();
+        history.record(x_wins(Variant::Misere, cid.id, bob.id)).await.unwrap();
+
+        let everyone = leaderboard.top(LeaderboardFilter { variant: None, since: None }, 10, 0).await.unwrap();
+        assert_eq!(everyone.iter().map(|standing| standing.id).collect::<Vec<_>>(), [ada.id, cid.id, bob.id]);
+        assert_eq!(everyone[2].form, "LL");
+
+        let misere = LeaderboardFilter { variant: Some(Variant::Misere), since: None };
+        let standings = leaderboard.top(misere, 10, 0).await.unwrap();
+        assert_eq!(standings.iter().map(|standing| (standing.id, standing.wins, standing.losses)).collect::<Vec<_>>(), [(cid.id, 1, 0), (bob.id, 0, 1)]);
+        let classic = LeaderboardFilter { variant: Some(Variant::default()), since: None };
+        assert!(leaderboard.standing(cid.id, classic).await.unwrap().is_none());
+        let wild = LeaderboardFilter { variant: Some(Variant::Wild), since: None };
+        assert!(leaderboard.top(wild, 10, 0).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use game_core::ai::Difficulty;
+use game_core::Player;
+use messages::game::server_message::Message as Com_Message;
+use messages::game::{RoomCreated, SearchStatus, ServerMessage};
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::{interval, Instant};
+use tracing::info;
+
+use crate::accounts::Profile;
+use crate::bot::Bot;
+use crate::config::{MatchmakingConfig, SessionConfig};
+use crate::history::MatchHistory;
+use crate::registry::{GameRegistry, Seat};
+use crate::session::{Connection, GameSession, MatchRules, Participant, SessionCommand, SessionHandle};
+
+struct WaitingPlayer {
+    player: Profile,
+    rules: MatchRules,
+    since: Instant,
+    connection: Connection,
+    call_me_back: oneshot::Sender<(Player, SessionHandle)>
+}
+
+// No 0/O or 1/I, so codes survive being read out loud.
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ROOM_CODE_LEN: usize = 5;
+// How often waiting players look again with a wider window.
+const SEARCH_TICK: Duration = Duration::from_secs(1);
+
+/// Pairs players who want the same variant and time control and are
+/// close in rating, accepting a wider gap the longer they wait, plus
+/// private rooms that only the holder of the code can join. Whoever
+/// waited plays X unless sides are configured to be random. Players
+/// left alone in the queue for too long get a bot instead.
+pub struct Matchmaker {
+    config: MatchmakingConfig,
+    session_config: SessionConfig,
+    registry: Arc<GameRegistry>,
+    history: Arc<MatchHistory>,
+    queue: Mutex<VecDeque<WaitingPlayer>>,
+    rooms: Mutex<HashMap<String, WaitingPlayer>>
+}
+
+impl Matchmaker {
+    pub fn new(config: MatchmakingConfig, session_config: SessionConfig, registry: Arc<GameRegistry>, history: Arc<MatchHistory>) -> Self {
+        Self {
+            config,
+            session_config,
+            registry,
+            history,
+            queue: Mutex::new(VecDeque::new()),
+            rooms: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Waits for an opponent close enough in rating and returns our
+    /// side together with the session both of us were seated in.
+    pub async fn find_game(&self, player: &Profile, rules: MatchRules, connection: Connection) -> Option<(Player, SessionHandle)> {
+        let mut queue = self.queue.lock().await;
+
+        // Drop whoever gave up waiting without us noticing the socket close.
+        queue.retain(|waiting| !waiting.call_me_back.is_closed());
+        if let Some(position) = self.closest(&queue, player, rules, connection.id, Duration::ZERO) {
+            let opponent = queue.remove(position)?;
+            drop(queue);
+
+            return Some(self.start_game(opponent, player, connection).await);
+        }
+
+        let connection_id = connection.id;
+        let outbound = connection.outbound.clone();
+        let since = Instant::now();
+        let (call_me_back, mut matched) = oneshot::channel();
+        queue.push_back(WaitingPlayer { player: player.clone(), rules, since, connection, call_me_back });
+        drop(queue);
+
+        let mut search = interval(SEARCH_TICK);
+        loop {
+            tokio::select! {
+                seat = &mut matched => return seat.ok(),
+                _ = search.tick() => {}
+            }
+            let waited = since.elapsed();
+            if self.config.bot_after.is_some_and(|bot_after| waited >= bot_after) {
+                break;
+            }
+
+            let mut queue = self.queue.lock().await;
+            if !queue.iter().any(|waiting| waiting.connection.id == connection_id) {
+                // Someone picked us in the meantime.
+                drop(queue);
+                return matched.await.ok();
+            }
+            if let Some(position) = self.closest(&queue, player, rules, connection_id, waited) {
+                let opponent = queue.remove(position)?;
+                let me = queue.iter().position(|waiting| waiting.connection.id == connection_id)?;
+                let waiting = queue.remove(me)?;
+                drop(queue);
+                return Some(self.start_game(opponent, player, waiting.connection).await);
+            }
+            drop(queue);
+
+            let status = SearchStatus { rating: player.rating, window: self.window(waited) };
+            outbound.send(ServerMessage { seq: 0, message: Some(Com_Message::SearchStatus(status)) }).await.ok();
+        }
+
+        // Nobody came. Unless someone picked us in the meantime, a bot takes the other seat.
+        let mut queue = self.queue.lock().await;
+        let Some(position) = queue.iter().position(|waiting| waiting.connection.id == connection_id) else {
+            drop(queue);
+            return matched.await.ok();
+        };
+        let waiting = queue.remove(position)?;
+        drop(queue);
+
+        info!("no opponent for player {} after {:?}", player.id, since.elapsed());
+        Some(self.play_bot(player, rules, waiting.connection, self.config.bot_difficulty).await)
+    }
+
+    /// Seats us against a bot straight away. We play X unless sides
+    /// are configured to be random.
+    pub async fn play_bot(&self, player: &Profile, rules: MatchRules, connection: Connection, difficulty: Difficulty) -> (Player, SessionHandle) {
+        let me = if self.config.random_sides && rand::random() {
+            Player::O
+        } else {
+            Player::X
+        };
+        let (bot, bot_connection) = Bot::new(difficulty);
+        let human = Participant { player_id: Some(player.id), label: player.label(), connection };
+        let bot_seat = Participant { player_id: None, label: Bot::label(difficulty), connection: bot_connection };
+        let session = match me {
+            Player::X => self.spawn_session(rules, human, bot_seat).await,
+            Player::O => self.spawn_session(rules, bot_seat, human).await
+        };
+        bot.spawn(session.clone());
+        self.registry.register(player.id, Seat { player: me, session: session.clone() }).await;
+
+        info!("player {} plays {me:?} against a {difficulty:?} bot", player.id);
+        (me, session)
+    }
+
+    /// Opens a room under a fresh code, tells the host the code and
+    /// waits until someone joins with it.
+    pub async fn create_room(&self, player: &Profile, rules: MatchRules, connection: Connection) -> Option<(Player, SessionHandle)> {
+        let outbound = connection.outbound.clone();
+        let (call_me_back, matched) = oneshot::channel();
+
+        let mut rooms = self.rooms.lock().await;
+        let code = loop {
+            let code = room_code();
+            if !rooms.contains_key(&code) {
+                break code;
+            }
+        };
+        rooms.insert(code.clone(), WaitingPlayer { player: player.clone(), rules, since: Instant::now(), connection, call_me_back });
+        drop(rooms);
+
+        info!("player {} opened room {code}", player.id);
+        let room_created = RoomCreated { code };
+        outbound.send(ServerMessage { seq: 0, message: Some(Com_Message::RoomCreated(room_created)) }).await.ok();
+        drop(outbound);
+
+        matched.await.ok()
+    }
+
+    /// Seats us with the host of the room. Hands the connection back if
+    /// there is no such room or the host already left.
+    pub async fn join_room(&self, code: &str, player: &Profile, connection: Connection) -> Result<(Player, SessionHandle), Connection> {
+        let code = code.trim().to_uppercase();
+        let host = self.rooms.lock().await.remove(&code);
+
+        match host {
+            Some(host) if !host.call_me_back.is_closed() => {
+                info!("player {} joined room {code}", player.id);
+                Ok(self.start_game(host, player, connection).await)
+            },
+            _ => Err(connection)
+        }
+    }
+
+    /// Takes a connection that closed while waiting out of the queue
+    /// and closes any room it was hosting.
+    pub async fn leave(&self, connection: u64) {
+        self.queue.lock().await.retain(|waiting| waiting.connection.id != connection);
+        self.rooms.lock().await.retain(|_, waiting| waiting.connection.id != connection);
+    }
+
+    /// The queued player with the same rules who is closest in rating to
+    /// `player`, leaving out the player themselves, who may be searching
+    /// from another tab too. The gap has to fit the wider of the two
+    /// search windows.
+    fn closest(&self, queue: &VecDeque<WaitingPlayer>, player: &Profile, rules: MatchRules, connection: u64, waited: Duration) -> Option<usize> {
+        let window = self.window(waited);
+        queue.iter()
+            .enumerate()
+            .filter(|(_, waiting)| waiting.rules == rules && waiting.connection.id != connection && waiting.player.id != player.id)
+            .filter(|(_, waiting)| player.rating.abs_diff(waiting.player.rating) <= window.max(self.window(waiting.since.elapsed())))
+            .min_by_key(|(_, waiting)| player.rating.abs_diff(waiting.player.rating))
+            .map(|(position, _)| position)
+    }
+
+    /// Rating points either way we accept after waiting this long.
+    fn window(&self, waited: Duration) -> u32 {
+        let growth = self.config.rating_window_growth.saturating_mul(waited.as_secs() as u32);
+        self.config.rating_window.saturating_add(growth)
+    }
+
+    /// Seats the waiting player and the newcomer in a fresh session and
+    /// lets the waiting one know.
+    async fn start_game(&self, waiting: WaitingPlayer, player: &Profile, connection: Connection) -> (Player, SessionHandle) {
+        let me = if self.config.random_sides && rand::random() {
+            Player::X
+        } else {
+            Player::O
+        };
+        let waiting_connection = waiting.connection.id;
+        let rules = waiting.rules;
+        let newcomer = Participant { player_id: Some(player.id), label: player.label(), connection };
+        let waiting_participant = Participant { player_id: Some(waiting.player.id), label: waiting.player.label(), connection: waiting.connection };
+        let session = match me {
+            Player::X => self.spawn_session(rules, newcomer, waiting_participant).await,
+            Player::O => self.spawn_session(rules, waiting_participant, newcomer).await
+        };
+        self.registry.register(player.id, Seat { player: me, session: session.clone() }).await;
+        self.registry.register(waiting.player.id, Seat { player: me.opposite(), session: session.clone() }).await;
+        if waiting.call_me_back.send((me.opposite(), session.clone())).is_err() {
+            // They left right after we checked, so treat it like any other disconnect.
+            session.send(SessionCommand::Disconnect { player: me.opposite(), connection: waiting_connection }).await;
+        }
+        (me, session)
+    }
+
+    /// Starts a session and makes it findable for spectators.
+    async fn spawn_session(&self, rules: MatchRules, x: Participant, o: Participant) -> SessionHandle {
+        let game_id = self.registry.next_game_id();
+        let session = GameSession::spawn(self.session_config.clone(), game_id, Arc::clone(&self.history), rules, x, o);
+        info!("game {game_id} started ({rules})");
+        self.registry.register_game(game_id, session.clone()).await;
+        session
+    }
+}
+
+fn room_code() -> String {
+    (0..ROOM_CODE_LEN)
+        .map(|_| ROOM_CODE_ALPHABET[rand::random_range(0..ROOM_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use game_core::{Rules, Variant};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::db::Database;
+
+    fn matchmaker(rating_window: u32, rating_window_growth: u32) -> Matchmaker {
+        let config = MatchmakingConfig {
+            random_sides: false,
+            bot_after: None,
+            bot_difficulty: Difficulty::Heuristic,
+            rating_window,
+            rating_window_growth
+        };
+        let session_config = SessionConfig { reconnect_grace: Duration::from_secs(30), rematch_window: Duration::from_secs(60) };
+        let history = MatchHistory::new(Database::open(Path::new(":memory:")).unwrap());
+        Matchmaker::new(config, session_config, Arc::new(GameRegistry::default()), Arc::new(history))
+    }
+
+    fn profile(id: u64, rating: i32) -> Profile {
+        Profile { id, display_name: None, rating, rated_games: 0 }
+    }
+
+    fn standard() -> MatchRules {
+        MatchRules { variant: Variant::Standard(Rules::default()), time_control: None }
+    }
+
+    fn waiting(player: Profile, rules: MatchRules, connection: u64, waited: Duration) -> WaitingPlayer {
+        WaitingPlayer {
+            player,
+            rules,
+            since: Instant::now() - waited,
+            connection: Connection::new(connection, mpsc::channel(1).0),
+            call_me_back: oneshot::channel().0
+        }
+    }
+
+    #[test]
+    fn window_grows_with_the_wait() {
+        let matchmaker = matchmaker(100, 10);
+        assert_eq!(matchmaker.window(Duration::ZERO), 100);
+        assert_eq!(matchmaker.window(Duration::from_millis(999)), 100);
+        assert_eq!(matchmaker.window(Duration::from_secs(1)), 110);
+        assert_eq!(matchmaker.window(Duration::from_secs(30)), 400);
+    }
+
+    #[test]
+    fn window_saturates() {
+        let matchmaker = matchmaker(100, u32::MAX / 2);
+        assert_eq!(matchmaker.window(Duration::from_secs(3)), u32::MAX);
+        assert_eq!(matchmaker.window(Duration::from_secs(60 * 60)), u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn closest_in_rating_within_the_window() {
+        let matchmaker = matchmaker(100, 10);
+        let queue = VecDeque::from([
+            waiting(profile(1, 1650), standard(), 1, Duration::ZERO),
+            waiting(profile(2, 1420), standard(), 2, Duration::ZERO),
+            waiting(profile(3, 1560), standard(), 3, Duration::ZERO)
+        ]);
+        assert_eq!(matchmaker.closest(&queue, &profile(4, 1500), standard(), 4, Duration::ZERO), Some(2));
+        // Nobody within 100 of 1800, until we have waited long enough.
+        assert_eq!(matchmaker.closest(&queue, &profile(4, 1800), standard(), 4, Duration::ZERO), None);
+        assert_eq!(matchmaker.closest(&queue, &profile(4, 1800), standard(), 4, Duration::from_secs(5)), Some(0));
+    }
+
+    #[tokio::test]
+    async fn either_window_will_do() {
+        let matchmaker = matchmaker(100, 10);
+        // They have waited long enough to accept us, even though we just came.
+        let queue = VecDeque::from([waiting(profile(1, 1500), standard(), 1, Duration::from_secs(10))]);
+        assert_eq!(matchmaker.closest(&queue, &profile(2, 1690), standard(), 2, Duration::ZERO), Some(0));
+        assert_eq!(matchmaker.closest(&queue, &profile(2, 1710), standard(), 2, Duration::ZERO), None);
+    }
+
+    #[tokio::test]
+    async fn only_the_same_rules() {
+        let matchmaker = matchmaker(100, 10);
+        let misere = MatchRules { variant: Variant::Misere, time_control: None };
+        let queue = VecDeque::from([waiting(profile(1, 1500), misere, 1, Duration::ZERO)]);
+        assert_eq!(matchmaker.closest(&queue, &profile(2, 1500), standard(), 2, Duration::ZERO), None);
+        assert_eq!(matchmaker.closest(&queue, &profile(2, 1500), misere, 2, Duration::ZERO), Some(0));
+    }
+
+    #[tokio::test]
+    async fn never_against_ourselves() {
+        let matchmaker = matchmaker(100, 10);
+        let queue = VecDeque::from([
+            waiting(profile(1, 1500), standard(), 1, Duration::ZERO),
+            waiting(profile(2, 1599), standard(), 2, Duration::ZERO)
+        ]);
+        // The same connection, or the same player searching from another tab.
+        assert_eq!(matchmaker.closest(&queue, &profile(1, 1500), standard(), 1, Duration::ZERO), Some(1));
+        assert_eq!(matchmaker.closest(&queue, &profile(1, 1500), standard(), 3, Duration::ZERO), Some(1));
+    }
+}
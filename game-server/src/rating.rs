@@ -0,0 +1,90 @@
+use game_core::Player;
+use rusqlite::{params, Transaction};
+use serde::Serialize;
+
+use crate::registry::PlayerId;
+
+/// Where every new player starts, matching the `players.rating` default.
+pub const INITIAL_RATING: i32 = 1500;
+
+// Newcomers move faster until their rating has had a chance to settle.
+const PROVISIONAL_GAMES: u32 = 20;
+const PROVISIONAL_K: f64 = 40.0;
+const K: f64 = 20.0;
+
+/// A player's rating after a game and how much it moved.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct RatingChange {
+    pub rating: i32,
+    pub delta: i32
+}
+
+/// Elo update for one side. `score` is 1 for a win, 0.5 for a draw and
+/// 0 for a loss.
+pub fn rate(rating: i32, rated_games: u32, opponent: i32, score: f64) -> i32 {
+    let expected = 1.0 / (1.0 + 10f64.powf(f64::from(opponent - rating) / 400.0));
+    let k = if rated_games < PROVISIONAL_GAMES { PROVISIONAL_K } else { K };
+    rating + (k * (score - expected)).round() as i32
+}
+
+/// Rates a game between two players with accounts as part of the
+/// transaction that records it. Returns the changes for X and O.
+pub fn rate_game(tx: &Transaction, x: PlayerId, o: PlayerId, winner: Option<Player>) -> rusqlite::Result<[RatingChange; 2]> {
+    let standing = |id: PlayerId| tx.query_row(
+        "SELECT rating, rated_games FROM players WHERE id = ?1",
+        params![id as i64],
+        |row| Ok((row.get::<_, i32>(0)?, row.get::<_, u32>(1)?))
+    );
+    let (x_rating, x_games) = standing(x)?;
+    let (o_rating, o_games) = standing(o)?;
+    let x_score = match winner {
+        Some(Player::X) => 1.0,
+        Some(Player::O) => 0.0,
+        None => 0.5
+    };
+
+    let x_new = rate(x_rating, x_games, o_rating, x_score);
+    let o_new = rate(o_rating, o_games, x_rating, 1.0 - x_score);
+    for (id, rating) in [(x, x_new), (o, o_new)] {
+        tx.execute(
+            "UPDATE players SET rating = ?1, rated_games = rated_games + 1 WHERE id = ?2",
+            params![rating, id as i64]
+        )?;
+    }
+    Ok([
+        RatingChange { rating: x_new, delta: x_new - x_rating },
+        RatingChange { rating: o_new, delta: o_new - o_rating }
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLED: u32 = PROVISIONAL_GAMES;
+
+    #[test]
+    fn equal_ratings_expect_a_draw() {
+        assert_eq!(rate(1500, SETTLED, 1500, 0.5), 1500);
+        assert_eq!(rate(1500, SETTLED, 1500, 1.0), 1500 + K as i32 / 2);
+        assert_eq!(rate(1500, SETTLED, 1500, 0.0), 1500 - K as i32 / 2);
+    }
+
+    #[test]
+    fn newcomers_move_faster() {
+        assert_eq!(rate(1500, 0, 1500, 1.0), 1520);
+        assert_eq!(rate(1500, PROVISIONAL_GAMES - 1, 1500, 1.0), 1520);
+        assert_eq!(rate(1500, PROVISIONAL_GAMES, 1500, 1.0), 1510);
+        assert_eq!(rate(1500, PROVISIONAL_GAMES - 1, 1500, 0.0), 1480);
+    }
+
+    #[test]
+    fn draw_moves_ratings_together() {
+        // 200 points apart, the stronger side was expected to score about 0.76.
+        assert_eq!(rate(1700, SETTLED, 1500, 0.5), 1695);
+        assert_eq!(rate(1500, SETTLED, 1700, 0.5), 1505);
+        // Beating a much weaker player is worth next to nothing.
+        assert_eq!(rate(2300, SETTLED, 1500, 1.0), 2300);
+        assert_eq!(rate(1500, SETTLED, 2300, 1.0), 1520);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use game_core::Player;
+use tokio::sync::Mutex;
+
+use crate::session::SessionHandle;
+
+pub type PlayerId = u64;
+pub type GameId = u64;
+
+#[derive(Clone)]
+pub struct Seat {
+    pub player: Player,
+    pub session: SessionHandle
+}
+
+/// Which game each player is sitting in, so a dropped connection
+/// can find its way back to the same session, and which games are
+/// running, so spectators can find them by id.
+pub struct GameRegistry {
+    seats: Mutex<HashMap<PlayerId, Seat>>,
+    games: Mutex<HashMap<GameId, SessionHandle>>,
+    next_game_id: AtomicU64
+}
+
+impl Default for GameRegistry {
+    fn default() -> Self {
+        Self {
+            seats: Mutex::new(HashMap::new()),
+            games: Mutex::new(HashMap::new()),
+            next_game_id: AtomicU64::new(1)
+        }
+    }
+}
+
+impl GameRegistry {
+    pub async fn register(&self, player_id: PlayerId, seat: Seat) {
+        self.seats.lock().await.insert(player_id, seat);
+    }
+
+    /// The player's seat in a game that is still running, if any.
+    pub async fn find(&self, player_id: PlayerId) -> Option<Seat> {
+        let mut seats = self.seats.lock().await;
+        match seats.get(&player_id) {
+            Some(seat) if seat.session.is_finished() => {
+                seats.remove(&player_id);
+                None
+            },
+            seat => seat.cloned()
+        }
+    }
+
+    pub fn next_game_id(&self) -> GameId {
+        self.next_game_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub async fn register_game(&self, game_id: GameId, session: SessionHandle) {
+        let mut games = self.games.lock().await;
+        games.retain(|_, session| !session.is_finished());
+        games.insert(game_id, session);
+    }
+
+    /// The session of a game that is still running, if any.
+    pub async fn find_game(&self, game_id: GameId) -> Option<SessionHandle> {
+        let mut games = self.games.lock().await;
+        match games.get(&game_id) {
+            Some(session) if session.is_finished() => {
+                games.remove(&game_id);
+                None
+            },
+            session => session.cloned()
+        }
+    }
+}
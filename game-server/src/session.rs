@@ -0,0 +1,630 @@
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use game_core::{Game, Move, Outcome, Player, Variant};
+use messages::game::server_message::Message as Com_Message;
+use messages::game::{
+    self, ErrorCode, GameFinished, GameOutcome, GameSnapshot, InitGame, OpponentLeft, OpponentReturned, PlayerMove, PlayerType, RatingChange,
+    RematchAccepted, RematchRequest, ServerMessage
+};
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info};
+
+use crate::clock::{Clock, TimeControl};
+use crate::config::SessionConfig;
+use crate::history::{GameRecord, MatchHistory, RecordedMove, RecordedPlayer};
+use crate::registry::{GameId, PlayerId};
+
+/// Everything players agree on before a match: what they play and how
+/// long they may think.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MatchRules {
+    pub variant: Variant,
+    pub time_control: Option<TimeControl>
+}
+
+impl From<MatchRules> for game::Rules {
+    fn from(rules: MatchRules) -> Self {
+        game::Rules {
+            time_control: rules.time_control.map(Into::into),
+            ..rules.variant.into()
+        }
+    }
+}
+
+impl fmt::Display for MatchRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time_control {
+            Some(time_control) => write!(f, "{}, {time_control}", self.variant),
+            None => write!(f, "{}", self.variant)
+        }
+    }
+}
+
+/// One websocket's way of receiving what the session sends. The id
+/// tells a stale connection apart from the one that replaced it.
+pub struct Connection {
+    pub id: u64,
+    pub outbound: mpsc::Sender<ServerMessage>,
+    // The seq of the last message the current game sent here.
+    seq: u32
+}
+
+impl Connection {
+    pub fn new(id: u64, outbound: mpsc::Sender<ServerMessage>) -> Self {
+        Connection { id, outbound, seq: 0 }
+    }
+
+    /// Sends the next message of the game. `false` once the socket is gone.
+    async fn send_numbered(&mut self, message: Com_Message) -> bool {
+        // Both carry everything there is to know, so the count starts over.
+        if matches!(message, Com_Message::InitGame(_) | Com_Message::GameSnapshot(_)) {
+            self.seq = 0;
+        }
+        self.seq += 1;
+        self.outbound.send(ServerMessage { seq: self.seq, message: Some(message) }).await.is_ok()
+    }
+}
+
+/// Someone taking a seat when the game starts, human or bot.
+pub struct Participant {
+    // `None` for bots.
+    pub player_id: Option<PlayerId>,
+    pub label: String,
+    pub connection: Connection
+}
+
+/// What connections ask of their session. Players are named by their
+/// seat, the side they played in the first game, as sides swap with
+/// every rematch.
+pub enum SessionCommand {
+    // `seq` is the number of the move in the game.
+    Move { player: Player, player_move: PlayerMove, seq: u32 },
+    Rematch { player: Player },
+    Reconnect { player: Player, connection: Connection },
+    Disconnect { player: Player, connection: u64 },
+    Spectate { connection: Connection },
+    // A player or spectator lost track and wants the whole game again.
+    Resync { connection: u64 }
+}
+
+/// Cheap handle the player connections use to talk to their session.
+#[derive(Clone)]
+pub struct SessionHandle {
+    commands: mpsc::Sender<SessionCommand>
+}
+
+impl SessionHandle {
+    /// Returns `false` once the session has finished and stopped listening.
+    pub async fn send(&self, command: SessionCommand) -> bool {
+        self.commands.send(command).await.is_ok()
+    }
+
+    /// Seats a new connection in place of a dropped one. Hands the
+    /// connection back if the session is already over.
+    pub async fn reconnect(&self, player: Player, connection: Connection) -> Result<(), Connection> {
+        match self.commands.send(SessionCommand::Reconnect { player, connection }).await {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::SendError(SessionCommand::Reconnect { connection, .. })) => Err(connection),
+            Err(_) => unreachable!("send hands back the command it was given")
+        }
+    }
+
+    /// Starts streaming the game to a spectator. Hands the connection
+    /// back if the session is already over.
+    pub async fn spectate(&self, connection: Connection) -> Result<(), Connection> {
+        match self.commands.send(SessionCommand::Spectate { connection }).await {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::SendError(SessionCommand::Spectate { connection })) => Err(connection),
+            Err(_) => unreachable!("send hands back the command it was given")
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+/// Owns the authoritative board of a single match. Player connections
+/// only forward commands here and relay whatever the session sends back.
+pub struct GameSession {
+    config: SessionConfig,
+    id: GameId,
+    history: Arc<MatchHistory>,
+    board: Game,
+    // Everything played so far this game, for the history.
+    moves: Vec<RecordedMove>,
+    started_at: SystemTime,
+    started: Instant,
+    // Only in games with a time control. Starts with the game.
+    clock: Option<Clock>,
+    time_control: Option<TimeControl>,
+    // Labels and connections are kept by seat, see [`SessionCommand`].
+    x_id: Option<PlayerId>,
+    o_id: Option<PlayerId>,
+    x_label: String,
+    o_label: String,
+    x: Option<Connection>,
+    o: Option<Connection>,
+    // Whether the seats play the opposite sides this game.
+    swapped: bool,
+    series: Series,
+    // Set once a game is over; the session ends if no rematch starts by then.
+    rematch_until: Option<Instant>,
+    // The seat that asked for a rematch first.
+    wants_rematch: Option<Player>,
+    // Only ever sent to; they drop out once their socket closes.
+    spectators: Vec<Connection>,
+    // Set while someone is disconnected; they forfeit if they aren't back by then.
+    forfeit_at: Option<Instant>,
+    commands: mpsc::Receiver<SessionCommand>
+}
+
+impl GameSession {
+    pub fn spawn(config: SessionConfig, id: GameId, history: Arc<MatchHistory>, rules: MatchRules, x: Participant, o: Participant) -> SessionHandle {
+        let (tx, rx) = mpsc::channel(16);
+        let session = GameSession {
+            config,
+            id,
+            history,
+            board: Game::new(rules.variant),
+            moves: Vec::new(),
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            clock: None,
+            time_control: rules.time_control,
+            x_id: x.player_id,
+            o_id: o.player_id,
+            x_label: x.label,
+            o_label: o.label,
+            x: Some(x.connection),
+            o: Some(o.connection),
+            swapped: false,
+            series: Series::default(),
+            rematch_until: None,
+            wants_rematch: None,
+            spectators: Vec::new(),
+            forfeit_at: None,
+            commands: rx
+        };
+        tokio::spawn(session.run());
+
+        SessionHandle { commands: tx }
+    }
+
+    async fn run(mut self) {
+        self.start_game().await;
+
+        loop {
+            let command = tokio::select! {
+                command = self.commands.recv() => command,
+                _ = sleep_until(self.forfeit_at.unwrap_or_else(Instant::now)), if self.forfeit_at.is_some() => {
+                    self.forfeit().await;
+                    break;
+                },
+                _ = sleep_until(self.clock.as_ref().map_or_else(Instant::now, Clock::deadline)), if self.clock.is_some() => {
+                    if self.time_out().await.is_break() {
+                        break;
+                    }
+                    continue;
+                },
+                _ = sleep_until(self.rematch_until.unwrap_or_else(Instant::now)), if self.rematch_until.is_some() => {
+                    info!("no rematch in game {}", self.id);
+                    break;
+                }
+            };
+            let Some(command) = command else {
+                break;
+            };
+
+            let flow = match command {
+                SessionCommand::Move { player, player_move, seq } => self.handle_move(player, player_move, seq).await,
+                SessionCommand::Rematch { player } => self.handle_rematch(player).await,
+                SessionCommand::Reconnect { player, connection } => self.handle_reconnect(player, connection).await,
+                SessionCommand::Disconnect { player, connection } => self.handle_disconnect(player, connection).await,
+                SessionCommand::Spectate { connection } => self.handle_spectate(connection).await,
+                SessionCommand::Resync { connection } => self.handle_resync(connection).await
+            };
+            if flow.is_break() {
+                break;
+            }
+        }
+
+        debug!("game session {} finished", self.id);
+    }
+
+    /// Tells both players their side in a new game and starts the clock.
+    async fn start_game(&mut self) {
+        self.moves.clear();
+        self.started_at = SystemTime::now();
+        self.started = Instant::now();
+        for seat in [Player::X, Player::O] {
+            let game_init = InitGame {
+                your_player: PlayerType::from(self.side(seat)) as i32,
+                game_id: self.id,
+                rules: Some(self.rules().into())
+            };
+            self.send(seat, Com_Message::InitGame(game_init)).await;
+        }
+        let snapshot = self.snapshot(None);
+        self.send_spectators(Com_Message::GameSnapshot(snapshot)).await;
+        if let Some(time_control) = self.time_control {
+            let clock = Clock::start(time_control, Instant::now());
+            self.broadcast(Com_Message::ClockUpdate(clock.update(Instant::now()))).await;
+            self.clock = Some(clock);
+        }
+    }
+
+    async fn handle_move(&mut self, seat: Player, player_move: PlayerMove, seq: u32) -> ControlFlow<()> {
+        let expected = self.board.moves_played() as u32 + 1;
+        if seq < expected {
+            debug!("dropping move {seq} from {seat:?}, already at move {expected}");
+            return ControlFlow::Continue(());
+        }
+        if seq > expected {
+            // The client is ahead of the game, so it missed something.
+            debug!("move {seq} from {seat:?} is ahead of move {expected}, resyncing");
+            if let Some(connection) = self.seat_ref(seat).as_ref().map(|connection| connection.id) {
+                return self.handle_resync(connection).await;
+            }
+            return ControlFlow::Continue(());
+        }
+        let Some(mv) = messages::decode_move(&player_move, self.side(seat)) else {
+            debug!("refusing malformed move {player_move:?}");
+            self.refuse(seat, ErrorCode::MalformedMessage, player_move).await;
+            return ControlFlow::Continue(());
+        };
+        if self.rematch_until.is_some() {
+            debug!("refusing move from {:?} after the game ended", mv.player);
+            self.refuse(seat, ErrorCode::GameOver, player_move).await;
+            return ControlFlow::Continue(());
+        }
+
+        // A move that crossed paths with the deadline comes too late.
+        let now = Instant::now();
+        if self.clock.as_ref().is_some_and(|clock| clock.running() == mv.player && clock.deadline() <= now) {
+            return self.time_out().await;
+        }
+
+        match self.board.apply_move(mv) {
+            Err(err) => {
+                debug!("rejected move from {:?}: {err:?}", mv.player);
+                self.refuse(seat, err.into(), player_move).await;
+                ControlFlow::Continue(())
+            },
+            Ok(None) => {
+                self.moves.push(RecordedMove { mv, at: now.saturating_duration_since(self.started) });
+                let player_move = messages::encode_move(self.board.variant(), mv);
+                self.send(seat.opposite(), Com_Message::PlayerMove(player_move)).await;
+                self.send_spectators(Com_Message::PlayerMove(player_move)).await;
+                if let Some(clock) = &mut self.clock {
+                    clock.switch(now);
+                    let update = clock.update(now);
+                    self.broadcast(Com_Message::ClockUpdate(update)).await;
+                }
+                ControlFlow::Continue(())
+            },
+            Ok(Some(outcome)) => {
+                self.moves.push(RecordedMove { mv, at: now.saturating_duration_since(self.started) });
+                info!("game finished: {outcome:?}");
+                let finished = game_finished(&self.board, outcome, mv);
+                let winner = match outcome {
+                    Outcome::Win(side) => Some(side),
+                    Outcome::Draw => None
+                };
+                self.finish(finished, winner).await
+            }
+        }
+    }
+
+    /// Tells a player their move didn't count, so they can take it back.
+    async fn refuse(&mut self, seat: Player, code: ErrorCode, player_move: PlayerMove) {
+        let error = game::Error { code: code as i32, rejected_move: Some(player_move) };
+        self.send(seat, Com_Message::Error(error)).await;
+    }
+
+    async fn handle_rematch(&mut self, seat: Player) -> ControlFlow<()> {
+        if self.rematch_until.is_none() {
+            debug!("ignoring rematch request from {seat:?} during the game");
+            return ControlFlow::Continue(());
+        }
+        match self.wants_rematch {
+            Some(asked) if asked == seat => {},
+            Some(_) => {
+                info!("rematch in game {} ({})", self.id, self.rules());
+                self.swapped = !self.swapped;
+                self.board = Game::new(self.board.variant());
+                self.rematch_until = None;
+                self.wants_rematch = None;
+                for seat in [Player::X, Player::O] {
+                    self.send(seat, Com_Message::RematchAccepted(self.series.seen_by(seat))).await;
+                }
+                self.start_game().await;
+            },
+            None => {
+                self.wants_rematch = Some(seat);
+                self.send(seat.opposite(), Com_Message::RematchRequest(RematchRequest {})).await;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    async fn handle_reconnect(&mut self, player: Player, connection: Connection) -> ControlFlow<()> {
+        info!("{player:?} reconnected");
+        let was_away = self.seat(player).replace(connection).is_none();
+        let opponent_here = self.seat_ref(player.opposite()).is_some();
+        if opponent_here {
+            self.forfeit_at = None;
+        }
+
+        let snapshot = self.snapshot(Some(player));
+        self.send(player, Com_Message::GameSnapshot(snapshot)).await;
+        if let Some(clock) = &self.clock {
+            self.send(player, Com_Message::ClockUpdate(clock.update(Instant::now()))).await;
+        }
+
+        if !opponent_here {
+            self.send(player, Com_Message::OpponentLeft(self.opponent_left())).await;
+        } else if was_away {
+            self.send(player.opposite(), Com_Message::OpponentReturned(OpponentReturned {})).await;
+        }
+        ControlFlow::Continue(())
+    }
+
+    async fn handle_disconnect(&mut self, player: Player, connection: u64) -> ControlFlow<()> {
+        let seat = self.seat(player);
+        // A connection that was already replaced by a reconnect doesn't count.
+        if seat.as_ref().is_none_or(|current| current.id != connection) {
+            return ControlFlow::Continue(());
+        }
+        *seat = None;
+        if self.rematch_until.is_some() {
+            info!("{player:?} left after the game");
+            return ControlFlow::Break(());
+        }
+
+        info!("{player:?} disconnected, waiting {:?} for them to return", self.config.reconnect_grace);
+        if self.forfeit_at.is_none() {
+            self.forfeit_at = Some(Instant::now() + self.config.reconnect_grace);
+        }
+        self.send(player.opposite(), Com_Message::OpponentLeft(self.opponent_left())).await;
+        ControlFlow::Continue(())
+    }
+
+    async fn handle_spectate(&mut self, mut connection: Connection) -> ControlFlow<()> {
+        let snapshot = self.snapshot(None);
+        if connection.send_numbered(Com_Message::GameSnapshot(snapshot)).await {
+            if let Some(clock) = &self.clock {
+                connection.send_numbered(Com_Message::ClockUpdate(clock.update(Instant::now()))).await;
+            }
+            self.spectators.push(connection);
+            info!("spectator joined game {}, {} watching", self.id, self.spectators.len());
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Sends the game as it stands to whoever asked, seated or watching.
+    async fn handle_resync(&mut self, connection: u64) -> ControlFlow<()> {
+        let seat = [Player::X, Player::O].into_iter()
+            .find(|seat| self.seat_ref(*seat).as_ref().is_some_and(|seated| seated.id == connection));
+        let snapshot = self.snapshot(seat);
+        let update = self.clock.as_ref().map(|clock| clock.update(Instant::now()));
+        debug!("resyncing connection {connection} in game {}", self.id);
+        let target = match seat {
+            Some(seat) => self.seat(seat).as_mut(),
+            None => self.spectators.iter_mut().find(|spectator| spectator.id == connection)
+        };
+        if let Some(target) = target {
+            target.send_numbered(Com_Message::GameSnapshot(snapshot)).await;
+            if let Some(update) = update {
+                target.send_numbered(Com_Message::ClockUpdate(update)).await;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Nobody came back in time: whoever is still connected wins.
+    async fn forfeit(&mut self) {
+        let Some(winner) = [Player::X, Player::O].into_iter().find(|player| self.seat_ref(*player).is_some()) else {
+            info!("both players left, dropping the game");
+            return;
+        };
+
+        info!("{:?} forfeited by leaving", winner.opposite());
+        let finished = self.ended_off_board(GameOutcome::Abandoned, self.side(winner));
+        let outcome = finished.outcome();
+        self.broadcast(Com_Message::GameFinished(finished)).await;
+        self.record(outcome, Some(self.side(winner))).await;
+    }
+
+    /// The side to move ran out of time and loses.
+    async fn time_out(&mut self) -> ControlFlow<()> {
+        let Some(clock) = &self.clock else {
+            return ControlFlow::Continue(());
+        };
+        let loser = clock.running();
+        info!("{loser:?} ran out of time");
+        let finished = self.ended_off_board(GameOutcome::Timeout, loser.opposite());
+        self.finish(finished, Some(loser.opposite())).await
+    }
+
+    /// Announces the result and keeps the table open for a rematch, as
+    /// long as both players are still here.
+    async fn finish(&mut self, finished: GameFinished, winner: Option<Player>) -> ControlFlow<()> {
+        let outcome = finished.outcome();
+        self.broadcast(Com_Message::GameFinished(finished)).await;
+        self.record(outcome, winner).await;
+        self.series.record(winner.map(|side| self.seat_of(side)));
+        self.clock = None;
+        if self.x.is_none() || self.o.is_none() {
+            return ControlFlow::Break(());
+        }
+        self.rematch_until = Some(Instant::now() + self.config.rematch_window);
+        ControlFlow::Continue(())
+    }
+
+    /// Stores the game and tells each player how their rating moved,
+    /// if it was rated.
+    async fn record(&mut self, outcome: GameOutcome, winner: Option<Player>) {
+        let player = |seat: Player| RecordedPlayer {
+            id: match seat {
+                Player::X => self.x_id,
+                Player::O => self.o_id
+            },
+            label: self.label(seat).to_string()
+        };
+        let record = GameRecord {
+            rules: self.rules(),
+            x: player(self.seat_of(Player::X)),
+            o: player(self.seat_of(Player::O)),
+            outcome,
+            winner,
+            started_at: self.started_at,
+            finished_at: SystemTime::now(),
+            moves: std::mem::take(&mut self.moves)
+        };
+        let Some(changes) = self.history.record(record).await else {
+            return;
+        };
+        for (side, change) in [Player::X, Player::O].into_iter().zip(changes) {
+            let rating_change = RatingChange { rating: change.rating, delta: change.delta };
+            self.send(self.seat_of(side), Com_Message::RatingChange(rating_change)).await;
+        }
+    }
+
+    fn ended_off_board(&self, outcome: GameOutcome, winner: Player) -> GameFinished {
+        GameFinished {
+            outcome: outcome as i32,
+            winner: Some(PlayerType::from(winner) as i32),
+            final_move: None,
+            winning_line: Vec::new(),
+            board: messages::encode_board(&self.board)
+        }
+    }
+
+    fn rules(&self) -> MatchRules {
+        MatchRules { variant: self.board.variant(), time_control: self.time_control }
+    }
+
+    /// The whole game as seen by `player`, or by a spectator when `None`.
+    fn snapshot(&self, player: Option<Player>) -> GameSnapshot {
+        GameSnapshot {
+            your_player: player.map(|player| PlayerType::from(player) as i32).unwrap_or_default(),
+            turn: PlayerType::from(self.board.turn()) as i32,
+            board: messages::encode_board(&self.board),
+            game_id: self.id,
+            spectating: player.is_none(),
+            x_label: self.label(self.seat_of(Player::X)).to_string(),
+            o_label: self.label(self.seat_of(Player::O)).to_string(),
+            rules: Some(self.rules().into()),
+            forced_board: self.board.forced_board().map(|sub_board| sub_board as u32)
+        }
+    }
+
+    fn opponent_left(&self) -> OpponentLeft {
+        let remaining = self.forfeit_at
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .unwrap_or_default();
+        OpponentLeft { forfeit_in_secs: remaining.as_secs_f32().ceil() as u32 }
+    }
+
+    /// The side `seat` plays this game.
+    fn side(&self, seat: Player) -> Player {
+        if self.swapped {
+            seat.opposite()
+        } else {
+            seat
+        }
+    }
+
+    /// The seat playing `side` this game. Swapping back undoes a swap,
+    /// so this is the same mapping as [`Self::side`].
+    fn seat_of(&self, side: Player) -> Player {
+        self.side(side)
+    }
+
+    fn label(&self, seat: Player) -> &str {
+        match seat {
+            Player::X => &self.x_label,
+            Player::O => &self.o_label
+        }
+    }
+
+    fn seat(&mut self, player: Player) -> &mut Option<Connection> {
+        match player {
+            Player::X => &mut self.x,
+            Player::O => &mut self.o
+        }
+    }
+
+    fn seat_ref(&self, player: Player) -> &Option<Connection> {
+        match player {
+            Player::X => &self.x,
+            Player::O => &self.o
+        }
+    }
+
+    async fn send(&mut self, player: Player, message: Com_Message) {
+        // A closed or empty seat just means that player's connection is gone.
+        if let Some(connection) = self.seat(player) {
+            connection.send_numbered(message).await;
+        }
+    }
+
+    async fn send_spectators(&mut self, message: Com_Message) {
+        let mut watching = Vec::with_capacity(self.spectators.len());
+        for mut spectator in self.spectators.drain(..) {
+            if spectator.send_numbered(message.clone()).await {
+                watching.push(spectator);
+            }
+        }
+        self.spectators = watching;
+    }
+
+    async fn broadcast(&mut self, message: Com_Message) {
+        self.send(Player::X, message.clone()).await;
+        self.send(Player::O, message.clone()).await;
+        self.send_spectators(message).await;
+    }
+}
+
+/// Results between the same two players, kept by seat.
+#[derive(Default)]
+struct Series {
+    x_wins: u32,
+    o_wins: u32,
+    draws: u32
+}
+
+impl Series {
+    fn record(&mut self, winner: Option<Player>) {
+        match winner {
+            Some(Player::X) => self.x_wins += 1,
+            Some(Player::O) => self.o_wins += 1,
+            None => self.draws += 1
+        }
+    }
+
+    fn seen_by(&self, seat: Player) -> RematchAccepted {
+        let (wins, losses) = match seat {
+            Player::X => (self.x_wins, self.o_wins),
+            Player::O => (self.o_wins, self.x_wins)
+        };
+        RematchAccepted { wins, losses, draws: self.draws }
+    }
+}
+
+fn game_finished(board: &Game, outcome: Outcome, final_move: Move) -> GameFinished {
+    GameFinished {
+        outcome: GameOutcome::from(outcome) as i32,
+        winner: None,
+        final_move: Some(messages::encode_move(board.variant(), final_move)),
+        winning_line: board.winning_line()
+            .map(|(_, line)| line.cells().iter().map(|cell| *cell as u32).collect())
+            .unwrap_or_default(),
+        board: messages::encode_board(board)
+    }
+}
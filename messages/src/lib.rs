@@ -1,3 +1,263 @@
 pub mod game {
     include!(concat!(env!("OUT_DIR"), "/game.rs"));
-}
\ No newline at end of file
+}
+
+/// Bumped whenever a change to `game.proto` would be misread by the
+/// other side, like reusing a field number or changing what one means.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest version the server still talks to. Version 1 moves have
+/// no seq, and would all be dropped as repeats.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional features named in `Hello` and `Welcome`, for changes that
+/// old peers can safely ignore and so don't need a new version.
+pub mod capability {
+    pub const RATINGS: &str = "ratings";
+    pub const REMATCH: &str = "rematch";
+    pub const SPECTATING: &str = "spectating";
+    pub const CLOCKS: &str = "clocks";
+}
+
+impl From<game_core::Player> for game::PlayerType {
+    fn from(player: game_core::Player) -> Self {
+        match player {
+            game_core::Player::X => game::PlayerType::X,
+            game_core::Player::O => game::PlayerType::O
+        }
+    }
+}
+
+impl From<game::PlayerType> for game_core::Player {
+    fn from(player: game::PlayerType) -> Self {
+        match player {
+            game::PlayerType::X => game_core::Player::X,
+            game::PlayerType::O => game_core::Player::O
+        }
+    }
+}
+
+impl From<game_core::Outcome> for game::GameOutcome {
+    fn from(outcome: game_core::Outcome) -> Self {
+        match outcome {
+            game_core::Outcome::Win(game_core::Player::X) => game::GameOutcome::XWins,
+            game_core::Outcome::Win(game_core::Player::O) => game::GameOutcome::OWins,
+            game_core::Outcome::Draw => game::GameOutcome::Draw
+        }
+    }
+}
+
+impl From<game_core::MoveError> for game::ErrorCode {
+    fn from(err: game_core::MoveError) -> Self {
+        match err {
+            game_core::MoveError::NotYourTurn => game::ErrorCode::NotYourTurn,
+            game_core::MoveError::CellOccupied => game::ErrorCode::CellOccupied,
+            game_core::MoveError::GameOver => game::ErrorCode::GameOver,
+            game_core::MoveError::OutOfBounds
+            | game_core::MoveError::WrongSubBoard
+            | game_core::MoveError::WrongPiece
+            | game_core::MoveError::NumberUsed => game::ErrorCode::IllegalMove
+        }
+    }
+}
+
+impl From<game_core::ai::Difficulty> for game::BotDifficulty {
+    fn from(difficulty: game_core::ai::Difficulty) -> Self {
+        match difficulty {
+            game_core::ai::Difficulty::Random => game::BotDifficulty::Random,
+            game_core::ai::Difficulty::Heuristic => game::BotDifficulty::Heuristic,
+            game_core::ai::Difficulty::Perfect => game::BotDifficulty::Perfect
+        }
+    }
+}
+
+impl From<game::BotDifficulty> for game_core::ai::Difficulty {
+    fn from(difficulty: game::BotDifficulty) -> Self {
+        match difficulty {
+            game::BotDifficulty::Random => game_core::ai::Difficulty::Random,
+            game::BotDifficulty::Heuristic => game_core::ai::Difficulty::Heuristic,
+            game::BotDifficulty::Perfect => game_core::ai::Difficulty::Perfect
+        }
+    }
+}
+
+impl From<game_core::Cell> for game::CellState {
+    fn from(cell: game_core::Cell) -> Self {
+        match cell {
+            game_core::Cell::Empty => game::CellState::Empty,
+            game_core::Cell::Taken(game_core::Player::X) => game::CellState::TakenX,
+            game_core::Cell::Taken(game_core::Player::O) => game::CellState::TakenO,
+            // Boards only ever hold 1 to 9.
+            game_core::Cell::Number(number @ 1..=9) => {
+                game::CellState::try_from(game::CellState::Number1 as i32 + number as i32 - 1).unwrap_or_default()
+            },
+            game_core::Cell::Number(_) => game::CellState::Empty
+        }
+    }
+}
+
+impl From<game::CellState> for game_core::Cell {
+    fn from(cell: game::CellState) -> Self {
+        match cell {
+            game::CellState::Empty => game_core::Cell::Empty,
+            game::CellState::TakenX => game_core::Cell::Taken(game_core::Player::X),
+            game::CellState::TakenO => game_core::Cell::Taken(game_core::Player::O),
+            number => game_core::Cell::Number((number as i32 - game::CellState::Number1 as i32 + 1) as u8)
+        }
+    }
+}
+
+impl From<game_core::Variant> for game::Rules {
+    fn from(variant: game_core::Variant) -> Self {
+        match variant {
+            game_core::Variant::Standard(rules) => game::Rules {
+                board_size: rules.size() as u32,
+                win_length: rules.win_length() as u32,
+                variant: game::Variant::Standard as i32,
+                ..Default::default()
+            },
+            game_core::Variant::Ultimate => game::Rules { variant: game::Variant::Ultimate as i32, ..Default::default() },
+            game_core::Variant::Misere => game::Rules { variant: game::Variant::Misere as i32, ..Default::default() },
+            game_core::Variant::Wild => game::Rules { variant: game::Variant::Wild as i32, ..Default::default() },
+            game_core::Variant::Numerical => game::Rules { variant: game::Variant::Numerical as i32, ..Default::default() }
+        }
+    }
+}
+
+/// Variant from its wire form: classic when left out, `None` when out of range.
+pub fn decode_variant(rules: Option<&game::Rules>) -> Option<game_core::Variant> {
+    let Some(rules) = rules else {
+        return Some(game_core::Variant::default());
+    };
+    match game::Variant::try_from(rules.variant).ok()? {
+        game::Variant::Standard => {
+            game_core::Rules::new(rules.board_size as usize, rules.win_length as usize).map(game_core::Variant::Standard)
+        },
+        game::Variant::Ultimate => Some(game_core::Variant::Ultimate),
+        game::Variant::Misere => Some(game_core::Variant::Misere),
+        game::Variant::Wild => Some(game_core::Variant::Wild),
+        game::Variant::Numerical => Some(game_core::Variant::Numerical)
+    }
+}
+
+/// A variant's name in URLs and the database, like `standard-15-5` or
+/// `misere`. Unlike its `Display` text, this never gets reworded.
+pub fn variant_slug(variant: game_core::Variant) -> String {
+    match variant {
+        game_core::Variant::Standard(rules) => format!("standard-{}-{}", rules.size(), rules.win_length()),
+        game_core::Variant::Ultimate => String::from("ultimate"),
+        game_core::Variant::Misere => String::from("misere"),
+        game_core::Variant::Wild => String::from("wild"),
+        game_core::Variant::Numerical => String::from("numerical")
+    }
+}
+
+/// The variant `variant_slug` gives this name, `None` if there is none.
+pub fn parse_variant_slug(slug: &str) -> Option<game_core::Variant> {
+    let variant = match slug {
+        "ultimate" => game_core::Variant::Ultimate,
+        "misere" => game_core::Variant::Misere,
+        "wild" => game_core::Variant::Wild,
+        "numerical" => game_core::Variant::Numerical,
+        _ => {
+            let (size, win_length) = slug.strip_prefix("standard-")?.split_once('-')?;
+            game_core::Variant::Standard(game_core::Rules::new(size.parse().ok()?, win_length.parse().ok()?)?)
+        }
+    };
+    // Each variant has one name only, so no "standard-03-3".
+    (variant_slug(variant) == slug).then_some(variant)
+}
+
+/// A move on the wire, split into sub-board and cell for ultimate games
+/// and carrying the piece put down in wild and numerical ones.
+pub fn encode_move(variant: game_core::Variant, mv: game_core::Move) -> game::PlayerMove {
+    let mut player_move = game::PlayerMove { cell: mv.cell as u32, ..Default::default() };
+    match (variant, mv.piece) {
+        (game_core::Variant::Ultimate, _) => {
+            let (sub_board, cell) = game_core::UltimateBoard::split(mv.cell);
+            player_move.cell = cell as u32;
+            player_move.sub_board = Some(sub_board as u32);
+        },
+        (game_core::Variant::Wild, game_core::Piece::Mark(mark)) => player_move.mark = Some(game::PlayerType::from(mark) as i32),
+        (_, game_core::Piece::Number(number)) => player_move.number = Some(number as u32),
+        _ => {}
+    }
+    player_move
+}
+
+/// `player`'s move, with the cell numbered like the variant's board
+/// does. `None` if a sub-board is given but the cell doesn't fit in it,
+/// or the piece is out of range. Whether the piece suits the variant
+/// is up to the board to check.
+pub fn decode_move(player_move: &game::PlayerMove, player: game_core::Player) -> Option<game_core::Move> {
+    let cell = player_move.cell as usize;
+    let cell = match player_move.sub_board {
+        None => cell,
+        Some(_) if cell >= game_core::SUB_BOARDS => return None,
+        Some(sub_board) => sub_board as usize * game_core::SUB_BOARDS + cell
+    };
+    let piece = match (player_move.mark, player_move.number) {
+        (None, None) => game_core::Piece::Mark(player),
+        (Some(mark), None) => game_core::Piece::Mark(game::PlayerType::try_from(mark).ok()?.into()),
+        (None, Some(number)) => game_core::Piece::Number(u8::try_from(number).ok()?),
+        (Some(_), Some(_)) => return None
+    };
+    Some(game_core::Move { cell, player, piece })
+}
+
+pub fn encode_board(game: &game_core::Game) -> Vec<i32> {
+    game.cells().iter().map(|cell| game::CellState::from(*cell) as i32).collect()
+}
+
+/// Rebuilds a game from its wire form, or `None` if it doesn't fit the
+/// variant. `forced_board` only matters for ultimate games.
+pub fn decode_board(variant: game_core::Variant, cells: &[i32], forced_board: Option<u32>) -> Option<game_core::Game> {
+    let mut board = [game_core::Cell::Empty; game_core::MAX_CELLS];
+    if cells.len() > board.len() {
+        return None;
+    }
+    for (decoded, cell) in board.iter_mut().zip(cells) {
+        *decoded = game::CellState::try_from(*cell).ok()?.into();
+    }
+    let cells = &board[..cells.len()];
+    match variant {
+        game_core::Variant::Standard(rules) => game_core::Board::from_cells(rules, cells).map(game_core::Game::Standard),
+        game_core::Variant::Ultimate => {
+            game_core::UltimateBoard::from_cells(cells, forced_board.map(|forced| forced as usize)).map(game_core::Game::Ultimate)
+        },
+        game_core::Variant::Misere => {
+            game_core::Board::from_cells(game_core::Rules::CLASSIC, cells).map(game_core::Game::Misere)
+        },
+        game_core::Variant::Wild => game_core::WildBoard::from_cells(cells).map(game_core::Game::Wild),
+        game_core::Variant::Numerical => game_core::NumericalBoard::from_cells(cells).map(game_core::Game::Numerical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_name_variants_both_ways() {
+        let variants = [
+            game_core::Variant::default(),
+            game_core::Variant::Standard(game_core::Rules::new(15, 5).unwrap()),
+            game_core::Variant::Ultimate,
+            game_core::Variant::Misere,
+            game_core::Variant::Wild,
+            game_core::Variant::Numerical
+        ];
+        for variant in variants {
+            assert_eq!(parse_variant_slug(&variant_slug(variant)), Some(variant));
+        }
+        assert_eq!(variant_slug(game_core::Variant::default()), "standard-3-3");
+        assert_eq!(variant_slug(game_core::Variant::Misere), "misere");
+    }
+
+    #[test]
+    fn unknown_slugs_name_nothing() {
+        for slug in ["", "misère", "3x3, 3 in a row", "standard", "standard-3", "standard-2-2", "standard-3-4", "standard-03-3", "Wild"] {
+            assert_eq!(parse_variant_slug(slug), None, "{slug}");
+        }
+    }
+}